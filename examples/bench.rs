@@ -0,0 +1,104 @@
+//! rough timings of the basic operations, std-only:
+//! cargo run --release --example bench
+extern crate hashmap;
+use hashmap::{CuckooMap, Hashmap, HopscotchMap, RobinHoodMap, SwissMap};
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+const N: u64 = 1_000_000;
+
+fn time(name: &str, f: impl FnOnce()) {
+    let start = Instant::now();
+    f();
+    println!("{:<10} {:>8.1} ms", name, start.elapsed().as_secs_f64() * 1e3);
+}
+
+// the same operations on each backend
+macro_rules! compare {
+    ($($map:ty),*) => {$(
+        println!("-- {}", stringify!($map));
+        let mut map = <$map>::default();
+        time("insert", || {
+            for i in 0..N {
+                map.insert(i, i);
+            }
+        });
+        time("get", || {
+            for i in 0..N {
+                black_box(map.get(&i));
+            }
+        });
+        time("miss", || {
+            for i in N..2 * N {
+                black_box(map.get(&i));
+            }
+        });
+        time("remove", || {
+            for i in 0..N {
+                black_box(map.remove(&i));
+            }
+        });
+    )*};
+}
+
+fn main() {
+    let mut map = Hashmap::new();
+    time("insert", || {
+        for i in 0..N {
+            map.insert(i, i);
+        }
+    });
+    time("get", || {
+        for i in 0..N {
+            black_box(map.get(&i));
+        }
+    });
+    time("miss", || {
+        for i in N..2 * N {
+            black_box(map.get(&i));
+        }
+    });
+    time("iter", || {
+        for _ in 0..10 {
+            black_box((&map).into_iter().map(|(_, v)| *v).sum::<u64>());
+        }
+    });
+    time("clone", || {
+        black_box(map.clone());
+    });
+    time("remove", || {
+        for i in 0..N {
+            black_box(map.remove(&i));
+        }
+    });
+
+    let mut strings = Hashmap::new();
+    time("insert str", || {
+        for i in 0..N / 4 {
+            strings.insert(format!("key-{}", i), i);
+        }
+    });
+
+    compare!(
+        Hashmap<u64, u64>,
+        RobinHoodMap<u64, u64>,
+        SwissMap<u64, u64>,
+        CuckooMap<u64, u64>,
+        HopscotchMap<u64, u64>
+    );
+
+    for incremental in [false, true] {
+        let mut map = Hashmap::new();
+        map.set_incremental_resize(incremental);
+        let mut worst = Duration::ZERO;
+        for i in 0..N {
+            let start = Instant::now();
+            map.insert(i, i);
+            worst = worst.max(start.elapsed());
+        }
+        let name = if incremental { "worst incr" } else { "worst" };
+        println!("{:<10} {:>8.1} ms", name, worst.as_secs_f64() * 1e3);
+    }
+}
@@ -0,0 +1,18 @@
+//! nothing here touches the OS (randomness, files, threads), so this builds
+//! and runs on wasm32-unknown-unknown as well:
+//! cargo build --example wasm --target wasm32-unknown-unknown
+extern crate hashmap;
+use hashmap::Hashmap;
+
+fn main() {
+    let mut word_counts = Hashmap::new();
+    for word in "the quick brown fox jumps over the lazy dog the end".split(' ') {
+        *word_counts.entry(word).or_insert(0) += 1;
+    }
+    assert_eq!(word_counts.get("the"), Some(&3));
+
+    // the default hasher has fixed keys: same input, same order, every run
+    let order: Vec<_> = (&word_counts).into_iter().map(|(word, _)| *word).collect();
+    let again: Vec<_> = (&word_counts).into_iter().map(|(word, _)| *word).collect();
+    assert_eq!(order, again);
+}
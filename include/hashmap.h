@@ -0,0 +1,34 @@
+/* C interface of the `ffi` feature, see src/ffi.rs */
+#ifndef RUSTY_HASHMAP_H
+#define RUSTY_HASHMAP_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct HashmapHandle HashmapHandle;
+
+HashmapHandle *hashmap_new(void);
+void hashmap_free(HashmapHandle *map);
+
+/* 1: replaced an existing value, 0: new key, -1: null map */
+int hashmap_insert(HashmapHandle *map, const uint8_t *key, size_t key_len,
+                   const uint8_t *value, size_t value_len);
+
+/* NULL if missing; the value stays valid until the map is modified */
+const uint8_t *hashmap_get(const HashmapHandle *map, const uint8_t *key,
+                           size_t key_len, size_t *value_len);
+
+/* 1: removed, 0: missing, -1: null map */
+int hashmap_remove(HashmapHandle *map, const uint8_t *key, size_t key_len);
+
+size_t hashmap_len(const HashmapHandle *map);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
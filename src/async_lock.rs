@@ -0,0 +1,213 @@
+//! a small runtime-agnostic async `RwLock` for `AsyncConcurrentHashmap`
+//!
+//! Waiting tasks park their waker and are all woken whenever the lock is
+//! released; a queued writer stops new readers from getting in, so writers
+//! don't starve behind a steady stream of reads.
+use std::{
+    cell::UnsafeCell,
+    future::Future,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::{Mutex, MutexGuard, PoisonError},
+    task::{Context, Poll, Waker},
+};
+
+pub(crate) struct RwLock<T> {
+    state: Mutex<State>,
+    value: UnsafeCell<T>,
+}
+
+#[derive(Default)]
+struct State {
+    readers: usize,
+    writer: bool,
+    writers_waiting: usize,
+    waiters: Vec<Waker>,
+}
+
+// same rules as std's RwLock: shared access hands out `&T` across threads
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub(crate) fn new(value: T) -> Self {
+        RwLock {
+            state: Mutex::new(State::default()),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn read(&self) -> Read<'_, T> {
+        Read { lock: self }
+    }
+
+    pub(crate) fn write(&self) -> Write<'_, T> {
+        Write {
+            lock: self,
+            queued: false,
+        }
+    }
+
+    fn release(&self, release: impl FnOnce(&mut State)) {
+        let mut state = self.state();
+        release(&mut state);
+        let waiters = std::mem::take(&mut state.waiters);
+        drop(state);
+        waiters.into_iter().for_each(Waker::wake);
+    }
+}
+
+pub(crate) struct Read<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T> Future for Read<'a, T> {
+    type Output = ReadGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.lock.state();
+        if !state.writer && state.writers_waiting == 0 {
+            state.readers += 1;
+            Poll::Ready(ReadGuard { lock: self.lock })
+        } else {
+            state.waiters.push(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+pub(crate) struct Write<'a, T> {
+    lock: &'a RwLock<T>,
+    // counted in `writers_waiting`
+    queued: bool,
+}
+
+impl<'a, T> Future for Write<'a, T> {
+    type Output = WriteGuard<'a, T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let lock = self.lock;
+        let mut state = lock.state();
+        if !state.writer && state.readers == 0 {
+            state.writer = true;
+            if self.queued {
+                state.writers_waiting -= 1;
+                self.queued = false;
+            }
+            Poll::Ready(WriteGuard { lock })
+        } else {
+            if !self.queued {
+                state.writers_waiting += 1;
+                self.queued = true;
+            }
+            state.waiters.push(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl<T> Drop for Write<'_, T> {
+    fn drop(&mut self) {
+        if self.queued {
+            // readers may have been held back by us
+            self.lock.release(|state| state.writers_waiting -= 1);
+        }
+    }
+}
+
+pub(crate) struct ReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release(|state| state.readers -= 1);
+    }
+}
+
+pub(crate) struct WriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for WriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release(|state| state.writer = false);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::{
+        sync::Arc,
+        task::Wake,
+        thread::{self, Thread},
+    };
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// just enough of an executor to drive one future on this thread
+    pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn readers_and_writers() {
+        let lock = RwLock::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        block_on(async {
+                            let mut guard = lock.write().await;
+                            *guard += 1;
+                        });
+                        block_on(async {
+                            assert!(*lock.read().await > 0);
+                        });
+                    }
+                });
+            }
+        });
+        assert_eq!(block_on(async { *lock.read().await }), 4000);
+    }
+}
@@ -0,0 +1,336 @@
+//! `ConcurrentHashmap` with async shard locks
+//!
+//! Waiting for a shard suspends the task instead of blocking the executor
+//! thread, so guards and entries can be held across `.await`. The lock is
+//! runtime-agnostic and works under tokio, async-std, etc.
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+    ops::{Deref, DerefMut},
+};
+
+use crate::{
+    async_lock::{ReadGuard, RwLock, WriteGuard},
+    concurrent::{shard_index, DEFAULT_SHARDS},
+    make_hash,
+    raw::Slot,
+    DefaultHashBuilder, Hashmap,
+};
+
+type Shard<K, V, S> = RwLock<Hashmap<K, V, S>>;
+
+pub struct AsyncConcurrentHashmap<K, V, S = DefaultHashBuilder> {
+    shards: Box<[Shard<K, V, S>]>,
+    hash_builder: S,
+}
+
+impl<K, V> AsyncConcurrentHashmap<K, V> {
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V> Default for AsyncConcurrentHashmap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, S> AsyncConcurrentHashmap<K, V, S>
+where
+    S: Clone,
+{
+    pub fn with_hasher(hash_builder: S) -> Self {
+        let shards = (0..DEFAULT_SHARDS)
+            .map(|_| RwLock::new(Hashmap::with_hasher(hash_builder.clone())))
+            .collect();
+        AsyncConcurrentHashmap {
+            shards,
+            hash_builder,
+        }
+    }
+}
+
+impl<K, V, S> AsyncConcurrentHashmap<K, V, S> {
+    pub async fn len(&self) -> usize {
+        let mut len = 0;
+        for shard in self.shards.iter() {
+            len += shard.read().await.len();
+        }
+        len
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+}
+
+impl<K, V, S> AsyncConcurrentHashmap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    fn shard<Q>(&self, key: &Q) -> &Shard<K, V, S>
+    where
+        Q: Hash + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        &self.shards[shard_index(hash, self.shards.len())]
+    }
+
+    pub async fn insert(&self, key: K, value: V) -> Option<V> {
+        self.shard(&key).write().await.insert(key, value)
+    }
+
+    pub async fn get<Q>(&self, key: &Q) -> Option<Ref<'_, K, V, S>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let guard = self.shard(key).read().await;
+        let slot = guard
+            .table
+            .find(make_hash(&guard.hash_builder, key), |(ekey, _)| ekey.borrow() == key)?;
+        Some(Ref { guard, slot })
+    }
+
+    pub async fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).read().await.contains_key(key)
+    }
+
+    pub async fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).write().await.remove(key)
+    }
+
+    /// Write-locks `key`'s shard until the entry (or the `RefMut` it turns
+    /// into) is dropped.
+    pub async fn entry(&self, key: K) -> Entry<'_, K, V, S> {
+        let guard = self.shard(&key).write().await;
+        let hash = make_hash(&guard.hash_builder, &key);
+        match guard.table.find(hash, |(ekey, _)| ekey == &key) {
+            Some(slot) => Entry::Occupied(OccupiedEntry { guard, slot }),
+            None => Entry::Vacant(VacantEntry { guard, hash, key }),
+        }
+    }
+}
+
+/// A read-locked entry, derefs to the value.
+pub struct Ref<'a, K, V, S> {
+    guard: ReadGuard<'a, Hashmap<K, V, S>>,
+    slot: Slot,
+}
+
+impl<K, V, S> Ref<'_, K, V, S> {
+    pub fn key(&self) -> &K {
+        &self.guard.table.slot(self.slot).0
+    }
+
+    pub fn value(&self) -> &V {
+        &self.guard.table.slot(self.slot).1
+    }
+}
+
+impl<K, V, S> Deref for Ref<'_, K, V, S> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        self.value()
+    }
+}
+
+pub enum Entry<'a, K, V, S> {
+    Occupied(OccupiedEntry<'a, K, V, S>),
+    Vacant(VacantEntry<'a, K, V, S>),
+}
+
+pub struct OccupiedEntry<'a, K, V, S> {
+    guard: WriteGuard<'a, Hashmap<K, V, S>>,
+    slot: Slot,
+}
+
+pub struct VacantEntry<'a, K, V, S> {
+    guard: WriteGuard<'a, Hashmap<K, V, S>>,
+    hash: u64,
+    key: K,
+}
+
+/// A write-locked entry, derefs (mutably) to the value.
+pub struct RefMut<'a, K, V, S> {
+    guard: WriteGuard<'a, Hashmap<K, V, S>>,
+    slot: Slot,
+}
+
+impl<'a, K, V, S> Entry<'a, K, V, S>
+where
+    K: Hash,
+    S: BuildHasher,
+{
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(e) => e.key(),
+            Entry::Vacant(e) => &e.key,
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> RefMut<'a, K, V, S> {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with(self, default: impl FnOnce() -> V) -> RefMut<'a, K, V, S> {
+        match self {
+            Entry::Occupied(e) => e.into_ref(),
+            Entry::Vacant(e) => e.insert(default()),
+        }
+    }
+
+    pub fn or_default(self) -> RefMut<'a, K, V, S>
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    pub fn and_modify(mut self, f: impl FnOnce(&mut V)) -> Self {
+        if let Entry::Occupied(e) = &mut self {
+            f(e.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, K, V, S> OccupiedEntry<'a, K, V, S> {
+    pub fn key(&self) -> &K {
+        &self.guard.table.slot(self.slot).0
+    }
+
+    pub fn get(&self) -> &V {
+        &self.guard.table.slot(self.slot).1
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.guard.table.slot_mut(self.slot).1
+    }
+
+    pub fn remove(mut self) -> V {
+        self.guard.table.erase(self.slot).1
+    }
+
+    pub fn into_ref(self) -> RefMut<'a, K, V, S> {
+        RefMut {
+            guard: self.guard,
+            slot: self.slot,
+        }
+    }
+}
+
+impl<'a, K, V, S> VacantEntry<'a, K, V, S>
+where
+    K: Hash,
+    S: BuildHasher,
+{
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn insert(mut self, value: V) -> RefMut<'a, K, V, S> {
+        let map = &mut *self.guard;
+        let slot = map
+            .table
+            .insert(self.hash, (self.key, value));
+        RefMut {
+            guard: self.guard,
+            slot,
+        }
+    }
+}
+
+impl<K, V, S> Deref for RefMut<'_, K, V, S> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        &self.guard.table.slot(self.slot).1
+    }
+}
+
+impl<K, V, S> DerefMut for RefMut<'_, K, V, S> {
+    fn deref_mut(&mut self) -> &mut V {
+        &mut self.guard.table.slot_mut(self.slot).1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_lock::tests::block_on;
+    use std::{
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll},
+        thread,
+    };
+
+    /// gives other tasks a chance to run, like `tokio::task::yield_now`
+    struct YieldNow(bool);
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                Poll::Ready(())
+            } else {
+                self.0 = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    #[test]
+    fn entry_held_across_await() {
+        let map = AsyncConcurrentHashmap::new();
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    block_on(async {
+                        for _ in 0..250 {
+                            let mut count = map.entry("count").await.or_insert(0);
+                            let seen = *count;
+                            YieldNow(false).await;
+                            *count = seen + 1;
+                        }
+                    })
+                });
+            }
+        });
+        block_on(async {
+            assert_eq!(*map.get("count").await.unwrap(), 1000);
+            assert_eq!(map.len().await, 1);
+            assert_eq!(map.remove("count").await, Some(1000));
+            assert!(!map.contains_key("count").await);
+            assert_eq!(map.insert("x", 1).await, None);
+        });
+    }
+
+    #[test]
+    fn futures_are_send() {
+        // what multi-threaded runtimes need to spawn a task holding a guard
+        fn assert_send<T: Send>(_: &T) {}
+        let map = AsyncConcurrentHashmap::<String, u64>::new();
+        let task = async {
+            let mut entry = map.entry(String::from("k")).await.or_default();
+            YieldNow(false).await;
+            *entry += 1;
+        };
+        assert_send(&task);
+        block_on(task);
+    }
+}
@@ -0,0 +1,154 @@
+//! a concurrent counter map whose values are `AtomicU64`s
+//!
+//! Incrementing a key that already exists only needs its shard's read
+//! lock, so hot counters bumped from many threads don't queue up behind a
+//! write lock. Only the first increment of a key takes the write lock.
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+};
+
+use crate::{
+    concurrent::{read, shard_index, write, DEFAULT_SHARDS},
+    make_hash, DefaultHashBuilder, Hashmap,
+};
+
+type Shard<K, S> = RwLock<Hashmap<K, AtomicU64, S>>;
+
+pub struct AtomicCounterMap<K, S = DefaultHashBuilder> {
+    shards: Box<[Shard<K, S>]>,
+    hash_builder: S,
+}
+
+impl<K> AtomicCounterMap<K> {
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<K> Default for AtomicCounterMap<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, S> AtomicCounterMap<K, S>
+where
+    S: Clone,
+{
+    pub fn with_hasher(hash_builder: S) -> Self {
+        let shards = (0..DEFAULT_SHARDS)
+            .map(|_| RwLock::new(Hashmap::with_hasher(hash_builder.clone())))
+            .collect();
+        AtomicCounterMap {
+            shards,
+            hash_builder,
+        }
+    }
+}
+
+impl<K, S> AtomicCounterMap<K, S> {
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| read(shard).len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| read(shard).is_empty())
+    }
+}
+
+impl<K, S> AtomicCounterMap<K, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    fn shard<Q>(&self, key: &Q) -> &Shard<K, S>
+    where
+        Q: Hash + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        &self.shards[shard_index(hash, self.shards.len())]
+    }
+
+    /// Adds `n` to `key`'s counter (starting from 0), returning the
+    /// previous value. The key is only cloned the first time it's seen.
+    pub fn fetch_add<Q>(&self, key: &Q, n: u64) -> u64
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let shard = self.shard(key);
+        if let Some(counter) = read(shard).get(key) {
+            return counter.fetch_add(n, Ordering::Relaxed);
+        }
+        write(shard)
+            .entry(key.to_owned())
+            .or_insert(AtomicU64::new(0))
+            .fetch_add(n, Ordering::Relaxed)
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<u64>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        read(self.shard(key))
+            .get(key)
+            .map(|counter| counter.load(Ordering::Relaxed))
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Option<u64>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        write(self.shard(key))
+            .remove(key)
+            .map(AtomicU64::into_inner)
+    }
+
+    /// Copies the current counts out, one shard at a time.
+    pub fn snapshot(&self) -> Hashmap<K, u64>
+    where
+        K: Clone,
+    {
+        let mut counts = Hashmap::new();
+        for shard in self.shards.iter() {
+            for (key, counter) in &*read(shard) {
+                counts.insert(key.clone(), counter.load(Ordering::Relaxed));
+            }
+        }
+        counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn counters() {
+        let map = AtomicCounterMap::<String>::new();
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for i in 0..1000 {
+                        map.fetch_add("requests", 1);
+                        map.fetch_add(if i % 2 == 0 { "even" } else { "odd" }, 2);
+                    }
+                });
+            }
+        });
+        assert_eq!(map.get("requests"), Some(4000));
+        assert_eq!(map.get("even"), Some(4000));
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.snapshot().get("odd"), Some(&4000));
+        assert_eq!(map.remove("odd"), Some(4000));
+        assert_eq!(map.get("odd"), None);
+    }
+}
@@ -0,0 +1,134 @@
+//! the storage a `Hashmap` sits on
+//!
+//! A `Hashmap` hashes keys and compares them; where entries go is up to
+//! its table, any `TableBackend`. The default is the chained `RawTable`;
+//! the open-addressing tables plug in through the aliases below and get
+//! the same map and `Entry` API. Extras that depend on the chained layout
+//! (`stats`, `retain`, `split_off_by`, ...) stay on the default backend.
+use crate::{
+    cuckoo::CuckooTable, hopscotch::HopscotchTable, robin_hood::RobinHoodTable, swiss::SwissTable,
+    DefaultHashBuilder, Hashmap,
+};
+
+/// A table of `(K, V)` entries addressed by precomputed hashes.
+///
+/// Tables never hash or compare keys themselves: callers pass the hash
+/// and an equality closure, and must `find` before inserting to keep keys
+/// unique.
+pub trait TableBackend<K, V>: Default {
+    /// where an entry sits; valid until the table is modified
+    type Slot: Copy;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// buckets or slots, as reported to `MetricsSink::record_resize`
+    fn bucket_count(&self) -> usize;
+
+    fn find(&self, hash: u64, eq: impl FnMut(&(K, V)) -> bool) -> Option<Self::Slot>;
+
+    /// `find`, also counting the entries it looked at on the way, whether
+    /// or not their hash matched. That is the probe length
+    /// `MetricsSink::record_probe` reports; a miss that looks at any
+    /// entry at all means a new key would collide.
+    fn find_probed(&self, hash: u64, eq: impl FnMut(&(K, V)) -> bool) -> (Option<Self::Slot>, usize);
+
+    fn slot(&self, slot: Self::Slot) -> &(K, V);
+
+    fn slot_mut(&mut self, slot: Self::Slot) -> &mut (K, V);
+
+    /// Makes room for `additional` more entries, so `insert_no_grow` won't
+    /// run out.
+    fn reserve(&mut self, additional: usize);
+
+    fn insert_no_grow(&mut self, hash: u64, entry: (K, V)) -> Self::Slot;
+
+    fn erase(&mut self, slot: Self::Slot) -> (K, V);
+
+    /// Grows to the next size up.
+    fn resize(&mut self);
+
+    /// The next run of entries stored side by side, starting at position
+    /// `*pos` or after, moving `*pos` past it; empty once there are no
+    /// more. Positions start at 0; walking them visits every entry once.
+    /// A dense table can hand out all its entries as one run.
+    ///
+    /// A cursor rather than an iterator type, since an associated type
+    /// would make `Hashmap`'s `Iter` invariant in `K` and `V`.
+    fn next_run(&self, pos: &mut usize) -> &[(K, V)];
+
+    /// A counter every modification bumps, which `Iter` checks to catch
+    /// the table changing under it (through unsafe code or interior
+    /// mutability). Debug builds only; tables without one return 0.
+    #[cfg(debug_assertions)]
+    fn generation(&self) -> u64 {
+        0
+    }
+}
+
+/// A `Hashmap` on a `RobinHoodTable`, for many small entries: no links,
+/// even probe lengths.
+pub type RobinHoodMap<K, V, S = DefaultHashBuilder> = Hashmap<K, V, S, RobinHoodTable<(K, V)>>;
+
+/// A `Hashmap` on a `SwissTable`, for lookup-heavy workloads.
+pub type SwissMap<K, V, S = DefaultHashBuilder> = Hashmap<K, V, S, SwissTable<(K, V)>>;
+
+/// A `Hashmap` on a `CuckooTable`, for read paths where the worst-case
+/// lookup matters more than insert throughput.
+pub type CuckooMap<K, V, S = DefaultHashBuilder> = Hashmap<K, V, S, CuckooTable<(K, V)>>;
+
+/// A `Hashmap` on a `HopscotchTable`, for bounded probe distances with
+/// good cache behavior.
+pub type HopscotchMap<K, V, S = DefaultHashBuilder> = Hashmap<K, V, S, HopscotchTable<(K, V)>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the same workload on any backend
+    fn exercise<B: TableBackend<String, usize>>(mut map: Hashmap<String, usize, DefaultHashBuilder, B>) {
+        for i in 0..2_000 {
+            assert_eq!(map.insert(i.to_string(), i), None);
+        }
+        *map.entry("7".to_string()).or_insert(0) += 1;
+        *map.entry("x".to_string()).or_insert(0) += 1;
+        assert_eq!(map.get("7"), Some(&8));
+        assert_eq!(map.get("x"), Some(&1));
+        for i in (0..2_000).step_by(2) {
+            assert_eq!(map.remove(i.to_string().as_str()), Some(i));
+        }
+        map.resize();
+        assert_eq!(map.len(), 1_001);
+        assert_eq!((&map).into_iter().count(), 1_001);
+        assert!(map.contains_key("1999"));
+        assert!(!map.contains_key("1998"));
+    }
+
+    fn empty<B: TableBackend<String, usize>>(mut map: Hashmap<String, usize, DefaultHashBuilder, B>) {
+        assert_eq!(map.get("a"), None);
+        assert!(!map.contains_key("a"));
+        assert_eq!(map.remove("a"), None);
+        assert_eq!((&map).into_iter().count(), 0);
+    }
+
+    #[test]
+    fn empty_backends() {
+        empty(Hashmap::<_, _>::new());
+        empty(RobinHoodMap::default());
+        empty(SwissMap::default());
+        empty(CuckooMap::default());
+        empty(HopscotchMap::default());
+    }
+
+    #[test]
+    fn backends() {
+        exercise(Hashmap::<_, _>::new());
+        exercise(RobinHoodMap::default());
+        exercise(SwissMap::default());
+        exercise(CuckooMap::default());
+        exercise(HopscotchMap::default());
+    }
+}
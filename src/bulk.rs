@@ -0,0 +1,509 @@
+//! whole-map operations: merging maps into each other, moving entries
+//! between them, splitting them, rewriting or converting every value,
+//! building one from items or parallel key/value lists, listing one in
+//! key order, collecting and extending, applying batches of operations
+use std::{
+    error::Error,
+    fmt,
+    hash::{BuildHasher, Hash},
+    marker::PhantomData,
+    mem,
+};
+
+use crate::{Entry, Hashmap, TableBackend};
+
+impl<K, V, S> Hashmap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Moves every entry of `other` into `self`. For keys both maps hold,
+    /// `resolve(key, mine, theirs)` decides what stays: leave `mine` alone
+    /// to keep it, assign `theirs` to replace it, or combine the two.
+    pub fn merge_with<S2>(
+        &mut self,
+        other: Hashmap<K, V, S2>,
+        mut resolve: impl FnMut(&K, &mut V, V),
+    ) {
+        for (key, theirs) in other {
+            match self.entry(key) {
+                Entry::Occupied(e) => {
+                    let (key, mine) = &mut *e.element;
+                    resolve(key, mine, theirs);
+                }
+                Entry::Vacant(e) => {
+                    e.insert(theirs);
+                }
+            }
+        }
+    }
+
+    /// Moves every entry of `other` into `self`, leaving `other` empty.
+    /// Values from `other` win for keys both maps hold.
+    pub fn append<S2>(&mut self, other: &mut Hashmap<K, V, S2>) {
+        // room for everything up front, so the table grows at most once
+        self.reserve(other.len());
+        for (key, value) in other.table.take_entries() {
+            self.insert(key, value);
+        }
+    }
+
+    /// `merge_with` for a borrowed `other`, cloning only the entries
+    /// `self` lacks.
+    pub fn merge_with_ref<S2>(
+        &mut self,
+        other: &Hashmap<K, V, S2>,
+        mut resolve: impl FnMut(&K, &mut V, &V),
+    ) where
+        S2: BuildHasher,
+        K: Clone,
+        V: Clone,
+    {
+        for (key, theirs) in other {
+            match self.get_mut(key) {
+                Some(mine) => resolve(key, mine, theirs),
+                None => {
+                    self.insert(key.clone(), theirs.clone());
+                }
+            }
+        }
+    }
+}
+
+impl<K, V, S> Hashmap<K, V, S> {
+    /// Rewrites every value through `f`; keys (and so the layout) are
+    /// left alone.
+    pub fn map_values_in_place(&mut self, mut f: impl FnMut(&K, &mut V)) {
+        for (key, value) in self.table.iter_mut() {
+            f(key, value);
+        }
+    }
+
+    /// `map_values_in_place` stopping at the first error, which leaves
+    /// the values visited before it rewritten.
+    pub fn try_map_values_in_place<E>(
+        &mut self,
+        mut f: impl FnMut(&K, &mut V) -> Result<(), E>,
+    ) -> Result<(), E> {
+        self.table.iter_mut().try_for_each(|(key, value)| f(key, value))
+    }
+}
+
+impl<K, V, S> Hashmap<K, V, S> {
+    /// Converts every value, keeping the keys where they are: no rehash,
+    /// no new allocation for the bucket array.
+    pub fn into_map_values<W>(self, mut f: impl FnMut(&K, V) -> W) -> Hashmap<K, W, S> {
+        Hashmap {
+            table: self.table.map(|(key, value)| {
+                let value = f(&key, value);
+                (key, value)
+            }),
+            hash_builder: self.hash_builder,
+            metrics: self.metrics,
+            marker: PhantomData,
+        }
+    }
+
+    /// Converts (or drops, on `None`) every entry into a new map. Keys
+    /// may change, so entries are rehashed into a table presized for all
+    /// of them, with this one's load factor and other settings.
+    pub fn into_filter_map<K2, W>(self, mut f: impl FnMut(K, V) -> Option<(K2, W)>) -> Hashmap<K2, W, S>
+    where
+        K2: Eq + Hash,
+        S: BuildHasher,
+    {
+        let mut map = Hashmap {
+            table: self.table.empty_like(),
+            hash_builder: self.hash_builder,
+            metrics: self.metrics,
+            marker: PhantomData,
+        };
+        map.reserve(self.table.len());
+        for (key, value) in self.table {
+            if let Some((key, value)) = f(key, value) {
+                map.insert(key, value);
+            }
+        }
+        map
+    }
+}
+
+impl<K, V, S, B> Extend<(K, V)> for Hashmap<K, V, S, B>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+    B: TableBackend<K, V>,
+{
+    /// Reserves for the iterator's lower size hint first, so the table
+    /// grows once instead of doubling its way up. Items may repeat keys
+    /// the map already has, so a non-empty map only reserves for half.
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, items: I) {
+        let items = items.into_iter();
+        let hint = items.size_hint().0;
+        self.reserve(if self.is_empty() { hint } else { hint.div_ceil(2) });
+        for (key, value) in items {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K, V, S, B> FromIterator<(K, V)> for Hashmap<K, V, S, B>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+    B: TableBackend<K, V>,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(items: I) -> Self {
+        let mut map = Self::default();
+        map.extend(items);
+        map
+    }
+}
+
+/// One step of a `Hashmap::apply_batch`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op<K, V> {
+    /// insert or replace
+    Insert(K, V),
+    Remove(K),
+    /// replace, only if the key is present
+    Update(K, V),
+}
+
+impl<K, V, S, B> Hashmap<K, V, S, B>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+    B: TableBackend<K, V>,
+{
+    /// Applies `ops` in order, growing the table at most once up front
+    /// (for every `Insert`, as if all its keys were new). Each op's result
+    /// is the value it displaced: the old value for `Insert`, `Update`
+    /// and `Remove`, `None` if the key was missing. An `Update` of a
+    /// missing key does nothing.
+    pub fn apply_batch(&mut self, ops: impl IntoIterator<Item = Op<K, V>>) -> Vec<Option<V>> {
+        let ops: Vec<_> = ops.into_iter().collect();
+        let inserts = ops.iter().filter(|op| matches!(op, Op::Insert(..))).count();
+        self.reserve(inserts);
+        ops.into_iter()
+            .map(|op| match op {
+                Op::Insert(key, value) => self.insert(key, value),
+                Op::Remove(key) => self.remove(&key),
+                Op::Update(key, value) => self.get_mut(&key).map(|old| mem::replace(old, value)),
+            })
+            .collect()
+    }
+}
+
+/// `Hashmap::from_keys_and_values` got more keys than values or the
+/// other way round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LengthMismatch {
+    /// pairs zipped before one side ran out
+    pub pairs: usize,
+}
+
+impl fmt::Display for LengthMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "keys and values differ in length after {} pairs", self.pairs)
+    }
+}
+
+impl Error for LengthMismatch {}
+
+impl<K, V, S> Hashmap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    /// Pairs up `keys` and `values` by position, presizing from their
+    /// size hints. Lengths must match: surplus keys or values are an
+    /// error, not silently dropped. A repeated key keeps its last value.
+    pub fn from_keys_and_values(
+        keys: impl IntoIterator<Item = K>,
+        values: impl IntoIterator<Item = V>,
+    ) -> Result<Self, LengthMismatch> {
+        let (mut keys, mut values) = (keys.into_iter(), values.into_iter());
+        let mut map = Hashmap::default();
+        map.reserve(keys.size_hint().0.min(values.size_hint().0));
+        // not `map.len()`, repeated keys don't add entries
+        let mut pairs = 0;
+        loop {
+            match (keys.next(), values.next()) {
+                (Some(key), Some(value)) => {
+                    map.insert(key, value);
+                    pairs += 1;
+                }
+                (None, None) => return Ok(map),
+                _ => return Err(LengthMismatch { pairs }),
+            }
+        }
+    }
+
+    /// Folds `items` into one value per key: `init` starts a key's value
+    /// the first time `key_fn` yields it, `fold` adds each item to it.
+    pub fn group_fold<T>(
+        items: impl IntoIterator<Item = T>,
+        mut key_fn: impl FnMut(&T) -> K,
+        mut init: impl FnMut() -> V,
+        mut fold: impl FnMut(&mut V, T),
+    ) -> Self {
+        let mut map = Hashmap::default();
+        for item in items {
+            let acc = match map.entry(key_fn(&item)) {
+                Entry::Occupied(e) => &mut e.element.1,
+                Entry::Vacant(e) => e.insert(init()),
+            };
+            fold(acc, item);
+        }
+        map
+    }
+}
+
+impl<K, T, S> Hashmap<K, Vec<T>, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    /// Collects `items` into groups by `key_fn`, each in iteration order.
+    pub fn group_by(items: impl IntoIterator<Item = T>, key_fn: impl FnMut(&T) -> K) -> Self {
+        Self::group_fold(items, key_fn, Vec::new, Vec::push)
+    }
+}
+
+impl<K: Ord, V, S> Hashmap<K, V, S> {
+    /// Every entry, ordered by key.
+    pub fn into_sorted_vec(self) -> Vec<(K, V)> {
+        let mut entries: Vec<_> = self.into_iter().collect();
+        // keys are unique, stability buys nothing
+        entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    /// References to every entry, ordered by key.
+    pub fn to_sorted_vec(&self) -> Vec<(&K, &V)> {
+        let mut entries: Vec<_> = self.into_iter().collect();
+        entries.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        entries
+    }
+}
+
+impl<K, V, S: Clone> Hashmap<K, V, S> {
+    /// Moves the entries `f` returns `true` for into a new map (with a
+    /// clone of the hasher, but no metrics sink), in one pass and without
+    /// rehashing. Each entry moved out counts as a removal from `self`.
+    pub fn split_off_by(&mut self, mut f: impl FnMut(&K, &V) -> bool) -> Self {
+        let metrics = self.metrics.as_deref();
+        let table = self.table.split_off_by(|(key, value)| {
+            let moved = f(key, value);
+            if let (true, Some(metrics)) = (moved, metrics) {
+                metrics.record_remove();
+            }
+            moved
+        });
+        Hashmap {
+            table,
+            hash_builder: self.hash_builder.clone(),
+            metrics: None,
+            marker: PhantomData,
+        }
+    }
+
+    /// Splits the map into the entries `f` returns `true` for and the
+    /// rest, moving them.
+    pub fn partition(mut self, f: impl FnMut(&K, &V) -> bool) -> (Self, Self) {
+        let matching = self.split_off_by(f);
+        (matching, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_with() {
+        let mut counts = Hashmap::new();
+        counts.insert("a", 1);
+        counts.insert("b", 2);
+        let mut other = Hashmap::new();
+        other.insert("b", 10);
+        other.insert("c", 3);
+
+        counts.merge_with_ref(&other, |_, mine, theirs| *mine += theirs);
+        assert_eq!(counts.get("b"), Some(&12));
+        assert_eq!(counts.get("c"), Some(&3));
+
+        // keep mine on conflict
+        counts.merge_with(other, |_, _, _| {});
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts.get("b"), Some(&12));
+        assert_eq!(counts.get("a"), Some(&1));
+    }
+
+    #[test]
+    fn map_values_in_place() {
+        let mut prices = Hashmap::new();
+        for i in 1..=10u32 {
+            prices.insert(i, i * 100);
+        }
+        prices.map_values_in_place(|_, price| *price += *price / 10);
+        assert_eq!(prices.get(&5), Some(&550));
+
+        let result = prices.try_map_values_in_place(|&k, price| {
+            if k == 3 {
+                return Err(k);
+            }
+            *price = 0;
+            Ok(())
+        });
+        assert_eq!(result, Err(3));
+        assert_eq!(prices.get(&3), Some(&330));
+    }
+
+    #[test]
+    fn into_map() {
+        let mut map = Hashmap::new();
+        for i in 0..50u32 {
+            map.insert(i, i);
+        }
+        let strings = map.clone().into_map_values(|k, v| format!("{}={}", k, v));
+        assert_eq!(strings.get(&7).map(String::as_str), Some("7=7"));
+        assert_eq!(strings.debug_validate(), Ok(()));
+
+        let inverted = map.into_filter_map(|k, v| (k % 5 == 0).then(|| (v * 2, k)));
+        assert_eq!(inverted.len(), 10);
+        assert_eq!(inverted.get(&20), Some(&10));
+        assert_eq!(inverted.debug_validate(), Ok(()));
+
+        // the new map keeps the load factor
+        let mut dense = Hashmap::with_max_load_factor(4.0);
+        for i in 0..1000u32 {
+            dense.insert(i, i);
+        }
+        let strings = dense.into_filter_map(|k, v| Some((k.to_string(), v)));
+        assert_eq!(strings.bucket_count(), 256);
+    }
+
+    #[test]
+    fn group_by() {
+        let words = ["apple", "avocado", "banana", "blueberry", "cherry"];
+        let by_letter: Hashmap<char, Vec<&str>> =
+            Hashmap::group_by(words, |w| w.chars().next().unwrap());
+        assert_eq!(by_letter.len(), 3);
+        assert_eq!(by_letter.get(&'b'), Some(&vec!["banana", "blueberry"]));
+
+        let lengths: Hashmap<char, usize> =
+            Hashmap::group_fold(words, |w| w.chars().next().unwrap(), || 0, |n, w| *n += w.len());
+        assert_eq!(lengths.get(&'a'), Some(&12));
+    }
+
+    #[test]
+    fn apply_batch() {
+        let mut map = Hashmap::new();
+        map.insert("a", 1);
+        let results = map.apply_batch([
+            Op::Insert("b", 2),
+            Op::Insert("a", 10),
+            Op::Update("c", 3),
+            Op::Update("b", 20),
+            Op::Remove("a"),
+            Op::Remove("a"),
+        ]);
+        assert_eq!(results, [None, Some(1), None, Some(2), Some(10), None]);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get("b"), Some(&20));
+    }
+
+    #[test]
+    fn collect() {
+        use crate::MetricsSink;
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        #[derive(Default)]
+        struct Resizes(AtomicUsize);
+        impl MetricsSink for Resizes {
+            fn record_resize(&self, _: usize, _: usize) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let resizes = Arc::new(Resizes::default());
+        let mut map = Hashmap::with_metrics(resizes.clone());
+        map.extend((0..10_000).map(|i| (i, i * 2)));
+        assert_eq!(resizes.0.load(Ordering::Relaxed), 1);
+        assert_eq!(map.len(), 10_000);
+
+        let collected: Hashmap<u32, u32> = (0..100).map(|i| (i % 10, i)).collect();
+        assert_eq!(collected.len(), 10);
+        assert_eq!(collected.get(&3), Some(&93));
+    }
+
+    #[test]
+    fn from_keys_and_values() {
+        let map: Hashmap<&str, u32> =
+            Hashmap::from_keys_and_values(["a", "b", "a"], [1, 2, 3]).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get("a"), Some(&3));
+
+        let short = Hashmap::<&str, u32>::from_keys_and_values(["a", "b", "c"], [1, 2]);
+        assert_eq!(short.err(), Some(LengthMismatch { pairs: 2 }));
+        assert!(Hashmap::<&str, u32>::from_keys_and_values(["a"], [1, 2]).is_err());
+    }
+
+    #[test]
+    fn sorted_vec() {
+        let mut map = Hashmap::new();
+        for word in ["pear", "apple", "fig"] {
+            map.insert(word, word.len());
+        }
+        assert_eq!(map.to_sorted_vec(), [(&"apple", &5), (&"fig", &3), (&"pear", &4)]);
+        assert_eq!(map.into_sorted_vec(), [("apple", 5), ("fig", 3), ("pear", 4)]);
+    }
+
+    #[test]
+    fn partition() {
+        let mut map = Hashmap::new();
+        for i in 0..100 {
+            map.insert(i, i.to_string());
+        }
+        let (even, odd) = map.partition(|k, _| k % 2 == 0);
+        assert_eq!((even.len(), odd.len()), (50, 50));
+        assert_eq!(even.get(&42).map(String::as_str), Some("42"));
+        assert!(odd.get(&42).is_none());
+        assert_eq!(even.debug_validate(), Ok(()));
+
+        let mut odd = odd;
+        let big = odd.split_off_by(|k, _| *k > 90);
+        assert_eq!(big.len(), 5);
+        assert_eq!(odd.len(), 45);
+        assert_eq!(odd.debug_validate(), Ok(()));
+    }
+
+    #[test]
+    fn append() {
+        let mut all = Hashmap::new();
+        all.insert(0, "zero");
+        let mut part = Hashmap::with_max_load_factor(4.0);
+        for i in 0..100 {
+            part.insert(i, "part");
+        }
+        all.append(&mut part);
+        assert!(part.is_empty());
+        assert_eq!(all.len(), 100);
+        assert_eq!(all.get(&0), Some(&"part"));
+        assert_eq!(all.debug_validate(), Ok(()));
+        part.insert(1, "reused");
+        assert_eq!(part.get(&1), Some(&"reused"));
+
+        // `part` keeps its load factor
+        for i in 0..1000 {
+            part.insert(i, "dense");
+        }
+        assert_eq!(part.bucket_count(), 256);
+        assert_eq!(part.debug_validate(), Ok(()));
+    }
+}
@@ -0,0 +1,141 @@
+//! the binary encoding used by the on-disk formats (`DurableMap`'s log,
+//! map snapshots)
+//!
+//! Deliberately tiny and serde-free: integers are little-endian, strings
+//! and byte vectors are stored as-is. Framing (lengths) is up to the
+//! format using it.
+use std::{error::Error, fmt};
+
+pub trait Codec: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+    /// `bytes` is exactly what `encode` produced
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    msg: &'static str,
+}
+
+impl DecodeError {
+    pub fn new(msg: &'static str) -> Self {
+        DecodeError { msg }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "decode error: {}", self.msg)
+    }
+}
+
+impl Error for DecodeError {}
+
+macro_rules! int_codec {
+    ($($t:ty),*) => {$(
+        impl Codec for $t {
+            fn encode(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+
+            fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+                bytes
+                    .try_into()
+                    .map(<$t>::from_le_bytes)
+                    .map_err(|_| DecodeError::new(concat!("wrong length for ", stringify!($t))))
+            }
+        }
+    )*};
+}
+
+int_codec!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl Codec for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        match bytes {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(DecodeError::new("invalid bool")),
+        }
+    }
+}
+
+impl Codec for Vec<u8> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl Codec for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::new("invalid utf-8"))
+    }
+}
+
+/// CRC-32 (IEEE), used to detect torn or corrupted data
+#[derive(Clone, Copy)]
+pub(crate) struct Crc32(u32);
+
+impl Crc32 {
+    pub(crate) fn new() -> Self {
+        Crc32(!0)
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u32;
+            for _ in 0..8 {
+                self.0 = (self.0 >> 1) ^ (0xedb8_8320 & (self.0 & 1).wrapping_neg());
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: Codec + PartialEq + fmt::Debug>(value: T) {
+        let mut buf = Vec::new();
+        value.encode(&mut buf);
+        assert_eq!(T::decode(&buf), Ok(value));
+    }
+
+    #[test]
+    fn codecs() {
+        round_trip(42u64);
+        round_trip(-7i32);
+        round_trip(true);
+        round_trip(String::from("héllo"));
+        round_trip(vec![0u8, 255, 3]);
+        assert!(u32::decode(&[1, 2]).is_err());
+        assert!(String::decode(&[0xff]).is_err());
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+}
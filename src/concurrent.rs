@@ -0,0 +1,826 @@
+//! a thread-safe map made of independently locked `Hashmap` shards
+//!
+//! A key's hash picks its shard, so operations on keys in different shards
+//! never wait on each other. Guards returned by `get` and `iter` hold their
+//! shard's read lock: don't write to the same map while holding one on the
+//! same thread, that deadlocks.
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+    ops::{Deref, DerefMut},
+    sync::{Arc, Condvar, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use crate::{make_hash, raw::Slot, DefaultHashBuilder, Hashmap};
+
+pub(crate) const DEFAULT_SHARDS: usize = 16;
+
+type Shard<K, V, S> = RwLock<Hashmap<K, V, S>>;
+type SharedReadGuard<'a, K, V, S> = Arc<RwLockReadGuard<'a, Hashmap<K, V, S>>>;
+type LockedEntries<'a, K, V, S> = (SharedReadGuard<'a, K, V, S>, crate::Iter<'a, K, V>);
+
+pub struct ConcurrentHashmap<K, V, S = DefaultHashBuilder> {
+    shards: Box<[Shard<K, V, S>]>,
+    hash_builder: S,
+    // `get_or_load` calls currently running a loader, by key
+    loads: Mutex<Hashmap<K, Arc<Load<V>>, S>>,
+}
+
+enum LoadState<V> {
+    Loading,
+    Done(V),
+    // the loader panicked, waiting callers start over
+    Failed,
+}
+
+struct Load<V> {
+    state: Mutex<LoadState<V>>,
+    finished: Condvar,
+}
+
+// a panic while a shard was locked doesn't make the shard unusable: the
+// map itself never leaves a half-finished operation behind on our side
+pub(crate) fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub(crate) fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// a few shards per CPU keeps two threads from often colliding on one
+fn default_shard_amount() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| (n.get() * 4).next_power_of_two())
+        .unwrap_or(DEFAULT_SHARDS)
+}
+
+/// which of `shard_count` (a power of two) shards `hash` belongs to
+pub(crate) fn shard_index(hash: u64, shard_count: usize) -> usize {
+    // buckets are picked by the low bits, use the high ones here
+    let bits = shard_count.trailing_zeros();
+    hash.checked_shr(u64::BITS - bits).unwrap_or(0) as usize
+}
+
+impl<K, V> ConcurrentHashmap<K, V> {
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+
+    /// See `with_shard_amount_and_hasher`.
+    pub fn with_shard_amount(shard_amount: usize) -> Self {
+        Self::with_shard_amount_and_hasher(shard_amount, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V> Default for ConcurrentHashmap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, S> ConcurrentHashmap<K, V, S>
+where
+    S: Clone,
+{
+    /// Uses a shard count based on the number of CPUs.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self::with_shard_amount_and_hasher(default_shard_amount(), hash_builder)
+    }
+
+    /// Panics unless `shard_amount` is a power of two.
+    pub fn with_shard_amount_and_hasher(shard_amount: usize, hash_builder: S) -> Self {
+        assert!(
+            shard_amount.is_power_of_two(),
+            "shard amount must be a power of two"
+        );
+        let shards = (0..shard_amount)
+            .map(|_| RwLock::new(Hashmap::with_hasher(hash_builder.clone())))
+            .collect();
+        ConcurrentHashmap {
+            shards,
+            loads: Mutex::new(Hashmap::with_hasher(hash_builder.clone())),
+            hash_builder,
+        }
+    }
+}
+
+impl<K, V, S> ConcurrentHashmap<K, V, S> {
+    /// Sum of the shard sizes; other threads may change it right away.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| read(shard).len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| read(shard).is_empty())
+    }
+
+    pub fn shard_amount(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// The number of entries in each shard, to spot a skewed hasher or a
+    /// hot shard.
+    pub fn shard_sizes(&self) -> Vec<usize> {
+        self.shards.iter().map(|shard| read(shard).len()).collect()
+    }
+
+    /// Iterates shard by shard, holding one shard's read lock at a time
+    /// (plus the locks of any guards still alive).
+    pub fn iter(&self) -> Iter<'_, K, V, S> {
+        Iter {
+            shards: self.shards.iter(),
+            current: None,
+        }
+    }
+
+    /// Keeps only the entries `f` returns `true` for, locking one shard at
+    /// a time so the rest of the map stays available meanwhile.
+    pub fn retain(&self, mut f: impl FnMut(&K, &mut V) -> bool) {
+        for shard in self.shards.iter() {
+            write(shard).retain(&mut f);
+        }
+    }
+
+    /// A point-in-time copy of every entry.
+    ///
+    /// All shards are read-locked together while copying (so the view is
+    /// consistent across shards) and released before this returns, so a
+    /// slow consumer never holds up writers.
+    pub fn iter_snapshot(&self) -> SnapshotIter<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let guards: Vec<_> = self.shards.iter().map(read).collect();
+        let mut entries = Vec::with_capacity(guards.iter().map(|g| g.len()).sum());
+        for guard in &guards {
+            entries.extend((&**guard).into_iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        drop(guards);
+        SnapshotIter {
+            entries: entries.into_iter(),
+        }
+    }
+}
+
+impl<K, V, S> ConcurrentHashmap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// The index (into `shard_sizes`) of the shard `key` lives in.
+    pub fn shard_for<Q>(&self, key: &Q) -> usize
+    where
+        Q: Hash + ?Sized,
+    {
+        shard_index(make_hash(&self.hash_builder, key), self.shards.len())
+    }
+
+    fn shard<Q>(&self, key: &Q) -> &Shard<K, V, S>
+    where
+        Q: Hash + ?Sized,
+    {
+        &self.shards[self.shard_for(key)]
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        write(self.shard(&key)).insert(key, value)
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<Ref<'_, K, V, S>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let guard = read(self.shard(key));
+        // the entry lives in the shard, not in the guard: it stays put for
+        // as long as the guard (kept next to it in `Ref`) is alive
+        let map: &Hashmap<K, V, S> = unsafe { &*(&*guard as *const _) };
+        let (key, value) = map
+            .table
+            .get(make_hash(&map.hash_builder, key), |(ekey, _)| ekey.borrow() == key)?;
+        Some(Ref {
+            _guard: guard,
+            key,
+            value,
+        })
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        read(self.shard(key)).contains_key(key)
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        write(self.shard(key)).remove(key)
+    }
+
+    /// Removes `key`'s entry if `pred` holds for it. The shard stays
+    /// locked from the check to the removal, so no other thread can change
+    /// the entry in between.
+    pub fn remove_if<Q>(&self, key: &Q, pred: impl FnOnce(&K, &V) -> bool) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        write(self.shard(key)).remove_if(key, pred)
+    }
+
+    /// Locks `key`'s shard for writing until the returned entry (or the
+    /// `RefMut` it turns into) is dropped, making read-modify-write on the
+    /// key atomic.
+    pub fn entry(&self, key: K) -> Entry<'_, K, V, S> {
+        let guard = write(self.shard(&key));
+        let hash = make_hash(&guard.hash_builder, &key);
+        match guard.table.find(hash, |(ekey, _)| ekey == &key) {
+            Some(slot) => Entry::Occupied(OccupiedEntry { guard, slot }),
+            None => Entry::Vacant(VacantEntry { guard, hash, key }),
+        }
+    }
+}
+
+impl<K, V, S> ConcurrentHashmap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Inserts `f(&key)` unless `key` is present, all under the shard's
+    /// write lock; `f` runs at most once even with racing callers.
+    pub fn compute_if_absent(&self, key: K, f: impl FnOnce(&K) -> V) -> RefMut<'_, K, V, S> {
+        match self.entry(key) {
+            Entry::Occupied(e) => e.into_ref(),
+            Entry::Vacant(e) => {
+                let value = f(e.key());
+                e.insert(value)
+            }
+        }
+    }
+
+    /// Replaces the value of a present `key` with `f(key, value)`, removing
+    /// the key if `f` returns `None`. Nothing happens for a missing key.
+    ///
+    /// If `f` panics the key is left removed.
+    pub fn compute_if_present<Q>(
+        &self,
+        key: &Q,
+        f: impl FnOnce(&K, V) -> Option<V>,
+    ) -> Option<RefMut<'_, K, V, S>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mut guard = write(self.shard(key));
+        let hash = make_hash(&guard.hash_builder, key);
+        let slot = guard.table.find(hash, |(ekey, _)| ekey.borrow() == key)?;
+        let (key, value) = guard.table.erase(slot);
+        let value = f(&key, value)?;
+        // the element we just took out left room for this one
+        let slot = guard.table.insert_no_grow(hash, (key, value));
+        Some(RefMut { guard, slot })
+    }
+
+    /// Sets `key` to `f(key, current value)`, where `None` means absent on
+    /// either side: it can insert, update or remove in one atomic step.
+    ///
+    /// If `f` panics on a present key, the key is left removed.
+    pub fn compute(
+        &self,
+        key: K,
+        f: impl FnOnce(&K, Option<V>) -> Option<V>,
+    ) -> Option<RefMut<'_, K, V, S>> {
+        match self.entry(key) {
+            Entry::Occupied(OccupiedEntry { mut guard, slot }) => {
+                let hash = make_hash(&guard.hash_builder, &guard.table.slot(slot).0);
+                let (key, value) = guard.table.erase(slot);
+                let value = f(&key, Some(value))?;
+                let slot = guard.table.insert_no_grow(hash, (key, value));
+                Some(RefMut { guard, slot })
+            }
+            Entry::Vacant(e) => {
+                let value = f(e.key(), None)?;
+                Some(e.insert(value))
+            }
+        }
+    }
+}
+
+impl<K, V, S> ConcurrentHashmap<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher,
+{
+    /// Returns `key`'s value, running `loader` to produce (and insert) it
+    /// if it is missing.
+    ///
+    /// Concurrent calls for the same missing key share one `loader` call:
+    /// the first caller runs it without holding any shard lock, the others
+    /// wait for its result. If that loader panics, one of the waiting
+    /// callers runs its own loader instead.
+    pub fn get_or_load(&self, key: K, loader: impl FnOnce(&K) -> V) -> V {
+        let mut loader = Some(loader);
+        loop {
+            if let Some(value) = self.get(&key) {
+                return value.clone();
+            }
+            let mut loads = self.loads.lock().unwrap_or_else(PoisonError::into_inner);
+            // a finished load inserts into the map before leaving `loads`
+            if let Some(value) = self.get(&key) {
+                return value.clone();
+            }
+            if let Some(load) = loads.get(&key).cloned() {
+                drop(loads);
+                if let Some(value) = load.wait() {
+                    return value;
+                }
+                continue;
+            }
+            let load = Arc::new(Load {
+                state: Mutex::new(LoadState::Loading),
+                finished: Condvar::new(),
+            });
+            loads.insert(key.clone(), Arc::clone(&load));
+            drop(loads);
+
+            let mut guard = LoadGuard {
+                map: self,
+                key: &key,
+                load: &load,
+                done: None,
+            };
+            let value = (loader.take().unwrap())(&key);
+            self.insert(key.clone(), value.clone());
+            guard.done = Some(value.clone());
+            return value;
+        }
+    }
+}
+
+impl<V: Clone> Load<V> {
+    /// `None` if the loader failed
+    fn wait(&self) -> Option<V> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            match &*state {
+                LoadState::Loading => {
+                    state = self.finished.wait(state).unwrap_or_else(PoisonError::into_inner)
+                }
+                LoadState::Done(value) => return Some(value.clone()),
+                LoadState::Failed => return None,
+            }
+        }
+    }
+}
+
+/// Publishes a load's outcome, including a panicking loader's.
+struct LoadGuard<'a, K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    map: &'a ConcurrentHashmap<K, V, S>,
+    key: &'a K,
+    load: &'a Load<V>,
+    done: Option<V>,
+}
+
+impl<K, V, S> Drop for LoadGuard<'_, K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    fn drop(&mut self) {
+        let mut loads = self.map.loads.lock().unwrap_or_else(PoisonError::into_inner);
+        loads.remove(self.key);
+        drop(loads);
+        let mut state = self.load.state.lock().unwrap_or_else(PoisonError::into_inner);
+        *state = match self.done.take() {
+            Some(value) => LoadState::Done(value),
+            None => LoadState::Failed,
+        };
+        self.load.finished.notify_all();
+    }
+}
+
+pub enum Entry<'a, K, V, S> {
+    Occupied(OccupiedEntry<'a, K, V, S>),
+    Vacant(VacantEntry<'a, K, V, S>),
+}
+
+pub struct OccupiedEntry<'a, K, V, S> {
+    guard: RwLockWriteGuard<'a, Hashmap<K, V, S>>,
+    slot: Slot,
+}
+
+pub struct VacantEntry<'a, K, V, S> {
+    guard: RwLockWriteGuard<'a, Hashmap<K, V, S>>,
+    hash: u64,
+    key: K,
+}
+
+/// A write-locked entry, derefs (mutably) to the value.
+pub struct RefMut<'a, K, V, S> {
+    guard: RwLockWriteGuard<'a, Hashmap<K, V, S>>,
+    slot: Slot,
+}
+
+impl<'a, K, V, S> Entry<'a, K, V, S>
+where
+    K: Hash,
+    S: BuildHasher,
+{
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(e) => e.key(),
+            Entry::Vacant(e) => e.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> RefMut<'a, K, V, S> {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with(self, default: impl FnOnce() -> V) -> RefMut<'a, K, V, S> {
+        match self {
+            Entry::Occupied(e) => e.into_ref(),
+            Entry::Vacant(e) => e.insert(default()),
+        }
+    }
+
+    pub fn or_default(self) -> RefMut<'a, K, V, S>
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Runs `f` on the value if the key is present, the lock stays held.
+    pub fn and_modify(mut self, f: impl FnOnce(&mut V)) -> Self {
+        if let Entry::Occupied(e) = &mut self {
+            f(e.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, K, V, S> OccupiedEntry<'a, K, V, S> {
+    pub fn key(&self) -> &K {
+        &self.guard.table.slot(self.slot).0
+    }
+
+    pub fn get(&self) -> &V {
+        &self.guard.table.slot(self.slot).1
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.guard.table.slot_mut(self.slot).1
+    }
+
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.get_mut(), value)
+    }
+
+    pub fn remove(mut self) -> V {
+        self.guard.table.erase(self.slot).1
+    }
+
+    pub fn into_ref(self) -> RefMut<'a, K, V, S> {
+        RefMut {
+            guard: self.guard,
+            slot: self.slot,
+        }
+    }
+}
+
+impl<'a, K, V, S> VacantEntry<'a, K, V, S>
+where
+    K: Hash,
+    S: BuildHasher,
+{
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn insert(mut self, value: V) -> RefMut<'a, K, V, S> {
+        let map = &mut *self.guard;
+        let slot = map
+            .table
+            .insert(self.hash, (self.key, value));
+        RefMut {
+            guard: self.guard,
+            slot,
+        }
+    }
+}
+
+impl<K, V, S> RefMut<'_, K, V, S> {
+    pub fn key(&self) -> &K {
+        &self.guard.table.slot(self.slot).0
+    }
+
+    pub fn value(&self) -> &V {
+        &self.guard.table.slot(self.slot).1
+    }
+
+    pub fn value_mut(&mut self) -> &mut V {
+        &mut self.guard.table.slot_mut(self.slot).1
+    }
+}
+
+impl<K, V, S> Deref for RefMut<'_, K, V, S> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        self.value()
+    }
+}
+
+impl<K, V, S> DerefMut for RefMut<'_, K, V, S> {
+    fn deref_mut(&mut self) -> &mut V {
+        self.value_mut()
+    }
+}
+
+/// A read-locked entry, derefs to the value.
+pub struct Ref<'a, K, V, S> {
+    _guard: RwLockReadGuard<'a, Hashmap<K, V, S>>,
+    key: &'a K,
+    value: &'a V,
+}
+
+impl<K, V, S> Ref<'_, K, V, S> {
+    pub fn key(&self) -> &K {
+        self.key
+    }
+
+    pub fn value(&self) -> &V {
+        self.value
+    }
+
+    pub fn pair(&self) -> (&K, &V) {
+        (self.key, self.value)
+    }
+}
+
+impl<K, V, S> Deref for Ref<'_, K, V, S> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        self.value
+    }
+}
+
+/// An entry yielded by `ConcurrentHashmap::iter`, keeps its shard
+/// read-locked while alive.
+pub struct RefMulti<'a, K, V, S> {
+    _guard: SharedReadGuard<'a, K, V, S>,
+    key: &'a K,
+    value: &'a V,
+}
+
+impl<K, V, S> RefMulti<'_, K, V, S> {
+    pub fn key(&self) -> &K {
+        self.key
+    }
+
+    pub fn value(&self) -> &V {
+        self.value
+    }
+
+    pub fn pair(&self) -> (&K, &V) {
+        (self.key, self.value)
+    }
+}
+
+impl<K, V, S> Deref for RefMulti<'_, K, V, S> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        self.value
+    }
+}
+
+pub struct Iter<'a, K, V, S> {
+    shards: std::slice::Iter<'a, Shard<K, V, S>>,
+    current: Option<LockedEntries<'a, K, V, S>>,
+}
+
+impl<'a, K, V, S> Iterator for Iter<'a, K, V, S> {
+    type Item = RefMulti<'a, K, V, S>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((guard, entries)) = &mut self.current {
+                if let Some((key, value)) = entries.next() {
+                    return Some(RefMulti {
+                        _guard: Arc::clone(guard),
+                        key,
+                        value,
+                    });
+                }
+            }
+            let guard = read(self.shards.next()?);
+            // see `ConcurrentHashmap::get`
+            let map: &'a Hashmap<K, V, S> = unsafe { &*(&*guard as *const _) };
+            self.current = Some((Arc::new(guard), map.into_iter()));
+        }
+    }
+}
+
+/// Owned entries copied by `ConcurrentHashmap::iter_snapshot`.
+pub struct SnapshotIter<K, V> {
+    entries: std::vec::IntoIter<(K, V)>,
+}
+
+impl<K, V> Iterator for SnapshotIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+impl<K, V> ExactSizeIterator for SnapshotIter<K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn shared_between_threads() {
+        let map = ConcurrentHashmap::new();
+        thread::scope(|s| {
+            for t in 0..4u64 {
+                let map = &map;
+                s.spawn(move || {
+                    for i in 0..250 {
+                        map.insert(t * 1000 + i, i);
+                    }
+                });
+            }
+        });
+        assert_eq!(map.len(), 1000);
+        assert_eq!(*map.get(&3042).unwrap(), 42);
+        assert_eq!(map.remove(&3042), Some(42));
+        assert!(map.get(&3042).is_none());
+        assert!(!map.contains_key(&3042));
+    }
+
+    #[test]
+    fn shards() {
+        let map = ConcurrentHashmap::with_shard_amount(4);
+        assert_eq!(map.shard_amount(), 4);
+        for i in 0..100 {
+            map.insert(i, ());
+        }
+        let sizes = map.shard_sizes();
+        assert_eq!(sizes.iter().sum::<usize>(), 100);
+        let in_first = (0..100).filter(|i| map.shard_for(i) == 0).count();
+        assert_eq!(sizes[0], in_first);
+
+        let single = ConcurrentHashmap::with_shard_amount(1);
+        single.insert("a", 1);
+        assert_eq!(single.shard_for("a"), 0);
+        assert!(ConcurrentHashmap::<u8, u8>::new().shard_amount().is_power_of_two());
+    }
+
+    #[test]
+    #[should_panic]
+    fn shard_amount_power_of_two() {
+        ConcurrentHashmap::<u8, u8>::with_shard_amount(3);
+    }
+
+    #[test]
+    fn iter() {
+        let map = ConcurrentHashmap::new();
+        for i in 0..100 {
+            map.insert(i, i * 2);
+        }
+        let mut seen: Vec<_> = map.iter().map(|e| (*e.key(), *e.value())).collect();
+        seen.sort();
+        assert_eq!(seen, (0..100).map(|i| (i, i * 2)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn iter_snapshot() {
+        let map = ConcurrentHashmap::new();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        let snapshot = map.iter_snapshot();
+        // no locks held: writers go ahead and the snapshot doesn't see them
+        map.insert(100, 100);
+        map.remove(&0);
+        assert_eq!(snapshot.len(), 100);
+        let mut keys: Vec<_> = snapshot.map(|(k, _)| k).collect();
+        keys.sort();
+        assert_eq!(keys, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn retain() {
+        let map = ConcurrentHashmap::new();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        map.retain(|k, v| {
+            *v *= 2;
+            k % 10 == 0
+        });
+        assert_eq!(map.len(), 10);
+        assert_eq!(*map.get(&30).unwrap(), 60);
+        assert_eq!(map.remove_if(&30, |_, v| *v == 0), None);
+        assert_eq!(map.remove_if(&30, |_, v| *v == 60), Some(60));
+    }
+
+    #[test]
+    fn get_or_load() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        let map = ConcurrentHashmap::new();
+        let loads = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    let value = map.get_or_load("config", |_| {
+                        loads.fetch_add(1, Ordering::SeqCst);
+                        // slow backend: everyone else piles up meanwhile
+                        thread::sleep(Duration::from_millis(50));
+                        42
+                    });
+                    assert_eq!(value, 42);
+                });
+            }
+        });
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+
+        // a panicking loader doesn't wedge the key
+        let result = std::panic::catch_unwind(|| map.get_or_load("bad", |_| panic!("backend down")));
+        assert!(result.is_err());
+        assert_eq!(map.get_or_load("bad", |_| 7), 7);
+    }
+
+    #[test]
+    fn compute() {
+        let map = ConcurrentHashmap::new();
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        map.compute("sum", |_, v| Some(v.unwrap_or(0) + 1));
+                        // racing inserts: only the first one gets to run
+                        map.compute_if_absent("once", |_| 1);
+                    }
+                });
+            }
+        });
+        assert_eq!(*map.get("sum").unwrap(), 400);
+        assert_eq!(*map.get("once").unwrap(), 1);
+
+        assert_eq!(*map.compute_if_present("sum", |_, v| Some(v * 2)).unwrap(), 800);
+        assert!(map.compute_if_present("sum", |_, _| None).is_none());
+        assert!(map.compute_if_present("sum", |_, v| Some(v)).is_none());
+        assert!(!map.contains_key("sum"));
+        assert!(map.compute("once", |_, _| None).is_none());
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn entry() {
+        let map = ConcurrentHashmap::new();
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        *map.entry("hits").or_insert(0) += 1;
+                    }
+                });
+            }
+        });
+        assert_eq!(*map.get("hits").unwrap(), 4000);
+
+        map.entry("hits").and_modify(|v| *v = 0).or_insert(7);
+        map.entry("misses").and_modify(|v| *v = 0).or_insert_with(|| 7);
+        assert_eq!(*map.get("hits").unwrap(), 0);
+        assert_eq!(*map.get("misses").unwrap(), 7);
+        match map.entry("misses") {
+            Entry::Occupied(e) => assert_eq!(e.remove(), 7),
+            Entry::Vacant(_) => unreachable!(),
+        }
+        assert!(!map.contains_key("misses"));
+    }
+}
@@ -0,0 +1,382 @@
+//! a cuckoo-hashing table
+//!
+//! Every element lives in one of exactly two slots, picked by the low and
+//! the high half of its hash, so a lookup probes two slots and never more.
+//! Inserting into two full slots evicts one of the occupants to its other
+//! slot, which may evict another, and so on. Chains that run too long,
+//! e.g. for elements whose hashes pick the same two slots, end in a small
+//! stash that lookups check after the two slots; the table grows once the
+//! stash fills up. Cuckoo tables stay at most half full, so this trades
+//! memory and insert speed for a flat worst-case lookup.
+//!
+//! `CuckooMap` is a `Hashmap` on `CuckooTable`.
+use std::{
+    iter::{Chain, Flatten},
+    mem, slice,
+};
+
+use crate::TableBackend;
+
+const INITIAL_CAPACITY: usize = 16;
+// evictions in a row before giving up and stashing
+const MAX_KICKS: usize = 32;
+const MAX_STASH: usize = 4;
+
+/// Position of an element inside a `CuckooTable`.
+///
+/// A slot stays valid until the table is modified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Slot {
+    // past the end of `slots` for elements in the stash
+    index: usize,
+}
+
+#[derive(Clone)]
+struct Bucket<T> {
+    hash: u64,
+    value: T,
+}
+
+type Slots<'a, T> = Flatten<slice::Iter<'a, Option<Bucket<T>>>>;
+
+/// Iterator over a `CuckooTable`'s elements, from `CuckooTable::iter`.
+pub struct Iter<'a, T> {
+    inner: Chain<Slots<'a, T>, slice::Iter<'a, Bucket<T>>>,
+}
+
+#[derive(Clone)]
+pub struct CuckooTable<T> {
+    // a power of two long, or empty
+    slots: Vec<Option<Bucket<T>>>,
+    stash: Vec<Bucket<T>>,
+    // stash size that triggers growing
+    stash_limit: usize,
+    items: usize,
+}
+
+impl<T> CuckooTable<T> {
+    pub const fn new() -> Self {
+        CuckooTable {
+            slots: Vec::new(),
+            stash: Vec::new(),
+            stash_limit: MAX_STASH,
+            items: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+
+    /// number of slots, always zero or a power of two
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// elements that found no place in either of their slots
+    pub fn stash_len(&self) -> usize {
+        self.stash.len()
+    }
+
+    /// the two slots `hash` may live in
+    fn positions(&self, hash: u64) -> [usize; 2] {
+        let mask = self.slots.len() - 1;
+        [hash as usize & mask, (hash >> 32) as usize & mask]
+    }
+
+    /// the slot other than `index` that `hash` may live in
+    fn alternative(&self, hash: u64, index: usize) -> usize {
+        match self.positions(hash) {
+            [first, second] if first == index => second,
+            [first, _] => first,
+        }
+    }
+
+    pub fn find(&self, hash: u64, eq: impl FnMut(&T) -> bool) -> Option<Slot> {
+        self.find_probed(hash, eq).0
+    }
+
+    /// `find`, also counting the occupied slots and stashed elements it
+    /// looked at
+    pub fn find_probed(&self, hash: u64, mut eq: impl FnMut(&T) -> bool) -> (Option<Slot>, usize) {
+        if self.slots.is_empty() {
+            return (None, 0);
+        }
+        let mut probes = 0;
+        for index in self.positions(hash) {
+            if let Some(bucket) = &self.slots[index] {
+                probes += 1;
+                if bucket.hash == hash && eq(&bucket.value) {
+                    return (Some(Slot { index }), probes);
+                }
+            }
+        }
+        let stashed = self.stash.iter().position(|bucket| {
+            probes += 1;
+            bucket.hash == hash && eq(&bucket.value)
+        });
+        let slot = stashed.map(|stashed| Slot {
+            index: self.slots.len() + stashed,
+        });
+        (slot, probes)
+    }
+
+    fn bucket(&self, slot: Slot) -> &Bucket<T> {
+        match slot.index.checked_sub(self.slots.len()) {
+            Some(stashed) => &self.stash[stashed],
+            None => self.slots[slot.index].as_ref().expect("stale slot"),
+        }
+    }
+
+    /// # Panics
+    ///
+    /// if `slot` doesn't point at an element (e.g. it is stale)
+    pub fn slot(&self, slot: Slot) -> &T {
+        &self.bucket(slot).value
+    }
+
+    /// # Panics
+    ///
+    /// if `slot` doesn't point at an element (e.g. it is stale)
+    pub fn slot_mut(&mut self, slot: Slot) -> &mut T {
+        let bucket = match slot.index.checked_sub(self.slots.len()) {
+            Some(stashed) => &mut self.stash[stashed],
+            None => self.slots[slot.index].as_mut().expect("stale slot"),
+        };
+        &mut bucket.value
+    }
+
+    /// Makes room for `additional` more elements, growing if the table
+    /// would get more than half full or the stash is full.
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = self.items + additional;
+        if needed <= self.slots.len() / 2 && self.stash.len() < self.stash_limit {
+            return;
+        }
+        let mut capacity = self.slots.len().max(INITIAL_CAPACITY);
+        while needed > capacity / 2 {
+            capacity *= 2;
+        }
+        if capacity == self.slots.len() {
+            capacity *= 2;
+        }
+        self.resize(capacity);
+    }
+
+    fn resize(&mut self, capacity: usize) {
+        let old = mem::replace(&mut self.slots, (0..capacity).map(|_| None).collect());
+        let stash = mem::take(&mut self.stash);
+        self.items = 0;
+        for bucket in old.into_iter().flatten().chain(stash) {
+            self.insert_no_grow(bucket.hash, bucket.value);
+        }
+        // elements with the same pair of slots stay stashed however big
+        // the table gets; don't grow again just for them
+        self.stash_limit = MAX_STASH.max(self.stash.len() * 2);
+    }
+
+    /// Inserts `value` under `hash` without checking for an equal element;
+    /// callers wanting map semantics must `find` first.
+    pub fn insert(&mut self, hash: u64, value: T) -> Slot {
+        self.reserve(1);
+        self.insert_no_grow(hash, value)
+    }
+
+    /// Like `insert`, for callers that already called `reserve`; may put
+    /// `value` or an element it evicts into the stash.
+    pub fn insert_no_grow(&mut self, hash: u64, value: T) -> Slot {
+        assert!(!self.slots.is_empty(), "CuckooTable has no slots");
+        self.items += 1;
+        let [first, second] = self.positions(hash);
+        let mut carried = Bucket { hash, value };
+        for index in [first, second] {
+            if self.slots[index].is_none() {
+                self.slots[index] = Some(carried);
+                return Slot { index };
+            }
+        }
+        // `value` ends up wherever it's left when not being carried
+        let mut ours = None;
+        let mut carrying_ours = true;
+        let mut index = first;
+        for _ in 0..MAX_KICKS {
+            let occupant = self.slots[index].as_mut().unwrap();
+            mem::swap(occupant, &mut carried);
+            let evicted_ours = ours == Some(index);
+            if carrying_ours {
+                ours = Some(index);
+            }
+            carrying_ours = evicted_ours;
+            index = self.alternative(carried.hash, index);
+            if self.slots[index].is_none() {
+                self.slots[index] = Some(carried);
+                if carrying_ours {
+                    ours = Some(index);
+                }
+                return Slot {
+                    index: ours.unwrap(),
+                };
+            }
+        }
+        self.stash.push(carried);
+        if carrying_ours {
+            ours = Some(self.slots.len() + self.stash.len() - 1);
+        }
+        Slot {
+            index: ours.unwrap(),
+        }
+    }
+
+    /// Removes the element at `slot`, moving a stashed element into the
+    /// freed slot if one fits there; that invalidates stash slots.
+    pub fn erase(&mut self, slot: Slot) -> T {
+        self.items -= 1;
+        if let Some(stashed) = slot.index.checked_sub(self.slots.len()) {
+            return self.stash.swap_remove(stashed).value;
+        }
+        let removed = self.slots[slot.index].take().expect("stale slot");
+        let fits = self
+            .stash
+            .iter()
+            .position(|bucket| self.positions(bucket.hash).contains(&slot.index));
+        if let Some(stashed) = fits {
+            self.slots[slot.index] = Some(self.stash.swap_remove(stashed));
+        }
+        removed.value
+    }
+
+    pub fn remove(&mut self, hash: u64, eq: impl FnMut(&T) -> bool) -> Option<T> {
+        let slot = self.find(hash, eq)?;
+        Some(self.erase(slot))
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            inner: self.slots.iter().flatten().chain(&self.stash),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> + '_ {
+        self.slots
+            .iter_mut()
+            .flatten()
+            .chain(&mut self.stash)
+            .map(|bucket| &mut bucket.value)
+    }
+}
+
+impl<T> Default for CuckooTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        self.inner.next().map(|bucket| &bucket.value)
+    }
+}
+
+impl<K, V> TableBackend<K, V> for CuckooTable<(K, V)> {
+    type Slot = Slot;
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn bucket_count(&self) -> usize {
+        self.capacity()
+    }
+
+    fn find(&self, hash: u64, eq: impl FnMut(&(K, V)) -> bool) -> Option<Slot> {
+        self.find(hash, eq)
+    }
+
+    fn find_probed(&self, hash: u64, eq: impl FnMut(&(K, V)) -> bool) -> (Option<Slot>, usize) {
+        self.find_probed(hash, eq)
+    }
+
+    fn slot(&self, slot: Slot) -> &(K, V) {
+        self.slot(slot)
+    }
+
+    fn slot_mut(&mut self, slot: Slot) -> &mut (K, V) {
+        self.slot_mut(slot)
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.reserve(additional)
+    }
+
+    fn insert_no_grow(&mut self, hash: u64, entry: (K, V)) -> Slot {
+        self.insert_no_grow(hash, entry)
+    }
+
+    fn erase(&mut self, slot: Slot) -> (K, V) {
+        self.erase(slot)
+    }
+
+    fn resize(&mut self) {
+        self.resize((self.capacity() * 2).max(INITIAL_CAPACITY))
+    }
+
+    fn next_run(&self, pos: &mut usize) -> &[(K, V)] {
+        // the slots, then the stash
+        for (index, bucket) in self.slots.iter().enumerate().skip(*pos) {
+            if let Some(bucket) = bucket {
+                *pos = index + 1;
+                return std::slice::from_ref(&bucket.value);
+            }
+        }
+        *pos = (*pos).max(self.slots.len());
+        let Some(bucket) = self.stash.get(*pos - self.slots.len()) else {
+            return &[];
+        };
+        *pos += 1;
+        std::slice::from_ref(&bucket.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table() {
+        let mut table = CuckooTable::new();
+        // the same two slots for everything: most of it gets stashed
+        for v in 0..10u64 {
+            let slot = table.insert(5 << 32 | 3, v);
+            assert_eq!(*table.slot(slot), v);
+        }
+        assert_eq!(table.stash_len(), 8);
+        assert_eq!(table.remove(5 << 32 | 3, |&e| e == 0), Some(0));
+        assert_eq!(table.stash_len(), 7);
+        for v in 1..10u64 {
+            assert!(table.find(5 << 32 | 3, |&e| e == v).is_some());
+        }
+        assert_eq!(table.iter().count(), 9);
+    }
+
+    #[test]
+    fn map() {
+        let mut map: crate::CuckooMap<_, _> = crate::CuckooMap::default();
+        for i in 0..10_000u32 {
+            assert_eq!(map.insert(i, i * 2), None);
+        }
+        assert_eq!(map.insert(7, 0), Some(14));
+        for i in (0..10_000).step_by(2) {
+            assert!(map.remove(&i).is_some());
+        }
+        assert_eq!(map.len(), 5_000);
+        assert_eq!(map.get(&7), Some(&0));
+        assert_eq!(map.get(&8), None);
+        assert_eq!((&map).into_iter().count(), 5_000);
+        assert!(map.table.stash_len() <= MAX_STASH);
+    }
+}
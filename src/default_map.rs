@@ -0,0 +1,169 @@
+//! a map that makes up missing values
+//!
+//! `DefaultHashmap` keeps a factory next to its entries and calls it for
+//! any key `get_mut` or `map[key]` asks for that isn't there yet, like
+//! Python's `defaultdict`. Accumulating code then reads
+//! `*counts.get_mut(word) += 1` instead of going through `entry`.
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+    ops::{Index, IndexMut},
+};
+
+use crate::{DefaultHashBuilder, Hashmap};
+
+#[derive(Clone)]
+pub struct DefaultHashmap<K, V, F = fn() -> V, S = DefaultHashBuilder> {
+    map: Hashmap<K, V, S>,
+    factory: F,
+}
+
+impl<K, V: Default> DefaultHashmap<K, V> {
+    /// a map filling in `V::default()`
+    pub fn new() -> Self {
+        Self::with_factory(V::default)
+    }
+}
+
+impl<K, V: Default> Default for DefaultHashmap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, F: FnMut() -> V> DefaultHashmap<K, V, F> {
+    pub fn with_factory(factory: F) -> Self {
+        Self::with_factory_and_hasher(factory, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, F: FnMut() -> V, S> DefaultHashmap<K, V, F, S> {
+    pub fn with_factory_and_hasher(factory: F, hash_builder: S) -> Self {
+        DefaultHashmap {
+            map: Hashmap::with_hasher(hash_builder),
+            factory,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> crate::Iter<'_, K, V> {
+        (&self.map).into_iter()
+    }
+
+    /// the map without its factory
+    pub fn into_inner(self) -> Hashmap<K, V, S> {
+        self.map
+    }
+}
+
+impl<K, V, F, S> DefaultHashmap<K, V, F, S>
+where
+    K: Eq + Hash,
+    F: FnMut() -> V,
+    S: BuildHasher,
+{
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.map.insert(key, value)
+    }
+
+    /// `key`'s value, inserting a new one from the factory if there is
+    /// none. The key is only cloned when it gets inserted.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> &mut V
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let factory = &mut self.factory;
+        self.map.get_or_insert_with(key, factory)
+    }
+
+    /// Doesn't insert: a missing key is `None`.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get(key)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.remove(key)
+    }
+}
+
+/// Reading can't insert, so `map[key]` on a shared map panics for a
+/// missing key; use `get` to avoid that.
+impl<K, V, F, S, Q> Index<&Q> for DefaultHashmap<K, V, F, S>
+where
+    K: Eq + Hash + Borrow<Q>,
+    F: FnMut() -> V,
+    S: BuildHasher,
+    Q: Hash + Eq + ?Sized,
+{
+    type Output = V;
+
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("no entry for key")
+    }
+}
+
+/// `map[key] = ...` and `map[key] += ...` insert a factory value first if
+/// `key` is missing.
+impl<K, V, F, S, Q> IndexMut<&Q> for DefaultHashmap<K, V, F, S>
+where
+    K: Eq + Hash + Borrow<Q>,
+    F: FnMut() -> V,
+    S: BuildHasher,
+    Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+{
+    fn index_mut(&mut self, key: &Q) -> &mut V {
+        self.get_mut(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_in() {
+        let mut counts = DefaultHashmap::<String, u32>::new();
+        for word in "a b a c a b".split(' ') {
+            *counts.get_mut(word) += 1;
+        }
+        assert_eq!(counts.get("a"), Some(&3));
+        assert_eq!(counts["b"], 2);
+        assert_eq!(counts.get("z"), None);
+        assert_eq!(counts.len(), 3);
+
+        let mut next = 0;
+        let mut ids = DefaultHashmap::with_factory(|| {
+            next += 1;
+            next
+        });
+        ids["x"] += 0;
+        ids["y"] += 0;
+        ids["x"] += 0;
+        assert_eq!(ids["y"], 2);
+        assert_eq!(ids.into_inner().len(), 2);
+    }
+}
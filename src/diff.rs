@@ -0,0 +1,274 @@
+//! comparing two maps entry by entry (or key by key), and replaying the
+//! difference
+use std::hash::{BuildHasher, Hash};
+
+use crate::{
+    codec::{Codec, DecodeError},
+    Hashmap,
+};
+
+/// What changed going from one map to another, see `Hashmap::diff`.
+///
+/// The entries borrow from both maps; each list is in the iteration
+/// order of the map it comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapDiff<'a, K, V> {
+    /// in the new map only
+    pub added: Vec<(&'a K, &'a V)>,
+    /// in the old map only
+    pub removed: Vec<(&'a K, &'a V)>,
+    /// in both with different values, as `(key, old, new)`
+    pub changed: Vec<(&'a K, &'a V, &'a V)>,
+}
+
+impl<K, V> MapDiff<'_, K, V> {
+    /// `true` if the two maps held the same entries
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// An owned set of changes for `Hashmap::apply`, e.g. from
+/// `MapDiff::to_patch`; implements `Codec` to be shipped elsewhere.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapPatch<K, V> {
+    /// entries to insert, replacing any current value
+    pub upserts: Vec<(K, V)>,
+    pub removals: Vec<K>,
+}
+
+impl<K, V> MapPatch<K, V> {
+    pub fn new() -> Self {
+        MapPatch {
+            upserts: Vec::new(),
+            removals: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.upserts.is_empty() && self.removals.is_empty()
+    }
+}
+
+impl<K, V> Default for MapPatch<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Clone, V: Clone> MapDiff<'_, K, V> {
+    /// The patch that, applied to the diff's old map, yields the new one.
+    pub fn to_patch(&self) -> MapPatch<K, V> {
+        let upserts = self
+            .added
+            .iter()
+            .map(|&(key, value)| (key.clone(), value.clone()))
+            .chain(self.changed.iter().map(|&(key, _, new)| (key.clone(), new.clone())))
+            .collect();
+        MapPatch {
+            upserts,
+            removals: self.removed.iter().map(|&(key, _)| key.clone()).collect(),
+        }
+    }
+}
+
+// u32-length-prefixed items, after a u32 item count for each list:
+// `upserts, (key, value) x upserts, removals, key x removals`
+fn put_chunk(out: &mut Vec<u8>, value: &impl Codec) {
+    let at = out.len();
+    out.extend_from_slice(&[0; 4]);
+    value.encode(out);
+    let len = (out.len() - at - 4) as u32;
+    out[at..at + 4].copy_from_slice(&len.to_le_bytes());
+}
+
+fn take_u32(bytes: &mut &[u8]) -> Result<u32, DecodeError> {
+    let Some((len, rest)) = bytes.split_first_chunk::<4>() else {
+        return Err(DecodeError::new("truncated patch"));
+    };
+    *bytes = rest;
+    Ok(u32::from_le_bytes(*len))
+}
+
+fn take_chunk<T: Codec>(bytes: &mut &[u8]) -> Result<T, DecodeError> {
+    let len = take_u32(bytes)? as usize;
+    if bytes.len() < len {
+        return Err(DecodeError::new("truncated patch"));
+    }
+    let (chunk, rest) = bytes.split_at(len);
+    *bytes = rest;
+    T::decode(chunk)
+}
+
+impl<K: Codec, V: Codec> Codec for MapPatch<K, V> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(self.upserts.len() as u32).to_le_bytes());
+        for (key, value) in &self.upserts {
+            put_chunk(out, key);
+            put_chunk(out, value);
+        }
+        out.extend_from_slice(&(self.removals.len() as u32).to_le_bytes());
+        for key in &self.removals {
+            put_chunk(out, key);
+        }
+    }
+
+    fn decode(mut bytes: &[u8]) -> Result<Self, DecodeError> {
+        let bytes = &mut bytes;
+        let mut patch = MapPatch::new();
+        // no presizing from counts that haven't been checked yet
+        for _ in 0..take_u32(bytes)? {
+            patch.upserts.push((take_chunk(bytes)?, take_chunk(bytes)?));
+        }
+        for _ in 0..take_u32(bytes)? {
+            patch.removals.push(take_chunk(bytes)?);
+        }
+        if !bytes.is_empty() {
+            return Err(DecodeError::new("trailing bytes after patch"));
+        }
+        Ok(patch)
+    }
+}
+
+impl<K, V, S> Hashmap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Applies `patch`'s removals, then its upserts.
+    pub fn apply(&mut self, patch: MapPatch<K, V>) {
+        for key in &patch.removals {
+            self.remove(key);
+        }
+        for (key, value) in patch.upserts {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K, V, S> Hashmap<K, V, S>
+where
+    K: Eq + Hash,
+    V: PartialEq,
+    S: BuildHasher,
+{
+    /// The changes turning `self` into `other`: entries only `other` has
+    /// are added, entries only `self` has are removed.
+    pub fn diff<'a, S2: BuildHasher>(&'a self, other: &'a Hashmap<K, V, S2>) -> MapDiff<'a, K, V> {
+        let mut diff = MapDiff {
+            added: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+        };
+        for (key, old) in self {
+            match other.get(key) {
+                None => diff.removed.push((key, old)),
+                Some(new) if new != old => diff.changed.push((key, old, new)),
+                Some(_) => {}
+            }
+        }
+        for (key, new) in other {
+            if !self.contains_key(key) {
+                diff.added.push((key, new));
+            }
+        }
+        diff
+    }
+}
+
+impl<K, V, S> Hashmap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Keys in `self` or `other`, each once: `self`'s first, then the
+    /// ones only `other` has. The value types may differ.
+    pub fn keys_union<'a, W, S2: BuildHasher>(
+        &'a self,
+        other: &'a Hashmap<K, W, S2>,
+    ) -> impl Iterator<Item = &'a K> + 'a {
+        let only_other = other.into_iter().map(|(key, _)| key).filter(|key| !self.contains_key(*key));
+        self.into_iter().map(|(key, _)| key).chain(only_other)
+    }
+
+    /// Keys in both maps, as stored in `self`.
+    pub fn keys_intersection<'a, W, S2: BuildHasher>(
+        &'a self,
+        other: &'a Hashmap<K, W, S2>,
+    ) -> impl Iterator<Item = &'a K> + 'a {
+        self.into_iter().map(|(key, _)| key).filter(|key| other.contains_key(*key))
+    }
+
+    /// Keys in `self` but not in `other`.
+    pub fn keys_difference<'a, W, S2: BuildHasher>(
+        &'a self,
+        other: &'a Hashmap<K, W, S2>,
+    ) -> impl Iterator<Item = &'a K> + 'a {
+        self.into_iter().map(|(key, _)| key).filter(|key| !other.contains_key(*key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff() {
+        let mut old = Hashmap::new();
+        old.insert("timeout", 30);
+        old.insert("retries", 3);
+        old.insert("verbose", 0);
+        let mut new = Hashmap::new();
+        new.insert("timeout", 60);
+        new.insert("retries", 3);
+        new.insert("color", 1);
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added, [(&"color", &1)]);
+        assert_eq!(diff.removed, [(&"verbose", &0)]);
+        assert_eq!(diff.changed, [(&"timeout", &30, &60)]);
+        assert!(old.diff(&old.clone()).is_empty());
+    }
+
+    #[test]
+    fn key_sets() {
+        let mut ports = Hashmap::new();
+        ports.insert("http", 80);
+        ports.insert("https", 443);
+        ports.insert("ssh", 22);
+        let mut names = Hashmap::new();
+        names.insert("http", String::from("web"));
+        names.insert("dns", String::from("resolver"));
+
+        fn sorted<'a>(keys: impl Iterator<Item = &'a &'static str>) -> Vec<&'static str> {
+            let mut keys: Vec<_> = keys.copied().collect();
+            keys.sort();
+            keys
+        }
+        assert_eq!(sorted(ports.keys_union(&names)), ["dns", "http", "https", "ssh"]);
+        assert_eq!(sorted(ports.keys_intersection(&names)), ["http"]);
+        assert_eq!(sorted(ports.keys_difference(&names)), ["https", "ssh"]);
+        assert_eq!(sorted(names.keys_difference(&ports)), ["dns"]);
+    }
+
+    #[test]
+    fn patch() {
+        let mut old = Hashmap::new();
+        let mut new = Hashmap::new();
+        for i in 0..20u32 {
+            old.insert(i, format!("v{}", i));
+            new.insert(i + 5, format!("v{}", i % 10));
+        }
+        let patch = old.diff(&new).to_patch();
+
+        let mut wire = Vec::new();
+        patch.encode(&mut wire);
+        let received = MapPatch::<u32, String>::decode(&wire).unwrap();
+        assert_eq!(received, patch);
+        assert!(MapPatch::<u32, String>::decode(&wire[..wire.len() - 1]).is_err());
+
+        let mut replica = old.clone();
+        replica.apply(received);
+        assert!(replica == new);
+    }
+}
@@ -0,0 +1,377 @@
+//! a `Hashmap` whose mutations are appended to a write-ahead log
+//!
+//! Every `insert`/`remove` is written to the log before it is applied, and
+//! `DurableMap::open` replays the log to rebuild the map after a restart.
+//! Once the log holds many more records than live entries it is compacted
+//! into one insert per entry.
+//!
+//! Log records are `len: u32, crc32: u32, payload` (little-endian), with
+//! payload `tag: u8, key_len: u32, key, value` (no value for removals).
+//! A torn or corrupt record at the tail (a crash mid-write) ends replay and
+//! is cut off. A bad record with good ones after it is damage to writes
+//! already made, and `open` refuses the log rather than drop them.
+use std::{
+    fs::{self, File, OpenOptions},
+    hash::Hash,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    codec::{crc32, Codec},
+    Hashmap,
+};
+
+const TAG_INSERT: u8 = 1;
+const TAG_REMOVE: u8 = 2;
+const DEFAULT_COMPACTION_THRESHOLD: usize = 1024;
+
+pub struct DurableMap<K, V> {
+    map: Hashmap<K, V>,
+    path: PathBuf,
+    log: File,
+    // bytes of whole records in `log`
+    log_len: u64,
+    records: usize,
+    compaction_threshold: usize,
+    // a failed automatic compaction, for the next `sync`/`compact`
+    compact_error: Option<io::Error>,
+}
+
+fn invalid(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+fn encode_record<K: Codec, V: Codec>(key: &K, value: Option<&V>) -> Vec<u8> {
+    let mut payload = vec![if value.is_some() { TAG_INSERT } else { TAG_REMOVE }];
+    payload.extend_from_slice(&[0; 4]);
+    key.encode(&mut payload);
+    let key_len = (payload.len() - 5) as u32;
+    payload[1..5].copy_from_slice(&key_len.to_le_bytes());
+    if let Some(value) = value {
+        value.encode(&mut payload);
+    }
+
+    let mut record = Vec::with_capacity(8 + payload.len());
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&crc32(&payload).to_le_bytes());
+    record.extend_from_slice(&payload);
+    record
+}
+
+/// The size, header included, the record at the start of `log` claims.
+fn record_len(log: &[u8]) -> Option<usize> {
+    Some(8 + u32::from_le_bytes(log.get(0..4)?.try_into().ok()?) as usize)
+}
+
+/// Splits the next well-formed record off `log`, `None` at the end or at a
+/// torn/corrupt record.
+fn next_record(log: &[u8]) -> Option<(&[u8], &[u8])> {
+    let len = record_len(log)? - 8;
+    let crc = u32::from_le_bytes(log.get(4..8)?.try_into().ok()?);
+    let payload = log.get(8..8 + len)?;
+    if crc32(payload) != crc || payload.len() < 5 {
+        return None;
+    }
+    Some((payload, &log[8 + len..]))
+}
+
+impl<K, V> DurableMap<K, V>
+where
+    K: Eq + Hash + Codec,
+    V: Codec,
+{
+    /// Opens (or creates) the log at `path` and replays it.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        let mut bytes = Vec::new();
+        log.read_to_end(&mut bytes)?;
+
+        let mut map = Hashmap::new();
+        let mut records = 0;
+        let mut largest = 0;
+        let mut rest = &bytes[..];
+        while let Some((payload, tail)) = next_record(rest) {
+            largest = largest.max(8 + payload.len());
+            let key_len = u32::from_le_bytes(payload[1..5].try_into().unwrap()) as usize;
+            let key_bytes = payload.get(5..5 + key_len).ok_or_else(|| invalid("key overruns record"))?;
+            let key = K::decode(key_bytes).map_err(invalid)?;
+            match payload[0] {
+                TAG_INSERT => {
+                    let value = V::decode(&payload[5 + key_len..]).map_err(invalid)?;
+                    map.insert(key, value);
+                }
+                TAG_REMOVE => {
+                    map.remove(&key);
+                }
+                _ => return Err(invalid("unknown record tag")),
+            }
+            records += 1;
+            rest = tail;
+        }
+        let log_len = (bytes.len() - rest.len()) as u64;
+        if !rest.is_empty() {
+            // a well-formed record past the bad one means this isn't a torn
+            // tail. Damage to one record leaves the next within a record's
+            // size of it, so only offsets and lengths up to the largest
+            // record (or the bad one's own length) are tried.
+            let window = match record_len(rest) {
+                Some(len) if len <= rest.len() => largest.max(len),
+                _ => largest,
+            };
+            let window = window.min(rest.len());
+            let damaged = (1..=window).any(|start| {
+                record_len(&rest[start..]).is_some_and(|len| len <= window)
+                    && next_record(&rest[start..]).is_some()
+            });
+            if damaged {
+                return Err(invalid(format!("corrupt record at byte {} of the log", log_len)));
+            }
+            log.set_len(log_len)?;
+        }
+
+        Ok(DurableMap {
+            map,
+            path,
+            log,
+            log_len,
+            records,
+            compaction_threshold: DEFAULT_COMPACTION_THRESHOLD,
+            compact_error: None,
+        })
+    }
+
+    /// The log is compacted once it holds at least `threshold` records and
+    /// more than twice as many records as live entries.
+    pub fn set_compaction_threshold(&mut self, threshold: usize) {
+        self.compaction_threshold = threshold;
+    }
+
+    fn append(&mut self, record: &[u8]) -> io::Result<()> {
+        if let Err(err) = self.log.write_all(record) {
+            // don't leave a partial record for later ones to follow
+            let _ = self.log.set_len(self.log_len);
+            return Err(err);
+        }
+        self.log_len += record.len() as u64;
+        self.records += 1;
+        Ok(())
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> io::Result<Option<V>> {
+        self.append(&encode_record(&key, Some(&value)))?;
+        let old = self.map.insert(key, value);
+        self.maybe_compact();
+        Ok(old)
+    }
+
+    pub fn remove(&mut self, key: &K) -> io::Result<Option<V>> {
+        if !self.map.contains_key(key) {
+            return Ok(None);
+        }
+        self.append(&encode_record::<K, V>(key, None))?;
+        let old = self.map.remove(key);
+        self.maybe_compact();
+        Ok(old)
+    }
+
+    // the write already succeeded, so a failure here is kept for the next
+    // `sync`/`compact` instead of failing it
+    fn maybe_compact(&mut self) {
+        if self.records >= self.compaction_threshold && self.records > 2 * self.map.len() {
+            if let Err(err) = self.rewrite() {
+                self.compact_error = Some(err);
+            }
+        }
+    }
+
+    /// Rewrites the log as one insert per live entry, atomically replacing
+    /// the old one. Fails with the error of an earlier automatic
+    /// compaction, if there was one, before trying again.
+    pub fn compact(&mut self) -> io::Result<()> {
+        if let Some(err) = self.compact_error.take() {
+            return Err(err);
+        }
+        self.rewrite()
+    }
+
+    fn rewrite(&mut self) -> io::Result<()> {
+        // a suffix, not an extension: that could name the log itself
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".compacting");
+        let tmp = PathBuf::from(tmp);
+        let mut out = File::create(&tmp)?;
+        let mut buf = Vec::new();
+        for (key, value) in &self.map {
+            buf.extend_from_slice(&encode_record(key, Some(value)));
+        }
+        out.write_all(&buf)?;
+        out.sync_all()?;
+        // opened before the rename so that, once it succeeds, nothing can
+        // fail between it and the switch to the new log
+        let log = OpenOptions::new().append(true).open(&tmp)?;
+        if let Err(err) = fs::rename(&tmp, &self.path) {
+            let _ = fs::remove_file(&tmp);
+            return Err(err);
+        }
+        self.log = log;
+        self.log_len = buf.len() as u64;
+        self.records = self.map.len();
+        // the rename itself is only durable once the directory is synced;
+        // other platforms can't open a directory to do that
+        #[cfg(unix)]
+        {
+            let dir = match self.path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the log to stable storage. Fails with the error of an
+    /// earlier automatic compaction, if there was one.
+    pub fn sync(&mut self) -> io::Result<()> {
+        if let Some(err) = self.compact_error.take() {
+            return Err(err);
+        }
+        self.log.sync_data()
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.map.get(key)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// the in-memory map, for reads the wrapper doesn't forward
+    pub fn map(&self) -> &Hashmap<K, V> {
+        &self.map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rusty_hashmap-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn replay() {
+        let path = temp_path("wal-replay");
+        {
+            let mut map = DurableMap::open(&path).unwrap();
+            map.insert(String::from("foo"), 1u32).unwrap();
+            map.insert(String::from("bar"), 2).unwrap();
+            map.insert(String::from("foo"), 3).unwrap();
+            assert_eq!(map.remove(&String::from("bar")).unwrap(), Some(2));
+        }
+        // simulate a crash in the middle of writing a record
+        let mut log = OpenOptions::new().append(true).open(&path).unwrap();
+        log.write_all(&[9, 0, 0, 0, 1, 2]).unwrap();
+
+        let map = DurableMap::<String, u32>::open(&path).unwrap();
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(&String::from("foo")), Some(&3));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_record_mid_log() {
+        let path = temp_path("wal-corrupt");
+        {
+            let mut map = DurableMap::open(&path).unwrap();
+            for i in 0..3u32 {
+                map.insert(i, i).unwrap();
+            }
+        }
+        let mut bytes = fs::read(&path).unwrap();
+        let len = bytes.len();
+        // a flipped bit in the second record's value
+        bytes[len * 2 / 3 - 1] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+
+        let err = DurableMap::<u32, u32>::open(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // nothing was cut off
+        assert_eq!(fs::read(&path).unwrap().len(), len);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn compaction() {
+        let path = temp_path("wal-compaction");
+        {
+            let mut map = DurableMap::open(&path).unwrap();
+            map.set_compaction_threshold(8);
+            for i in 0..100u64 {
+                map.insert(i % 4, i).unwrap();
+            }
+            assert!(map.records < 16);
+        }
+        let map = DurableMap::<u64, u64>::open(&path).unwrap();
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.get(&3), Some(&99));
+        fs::remove_file(&path).unwrap();
+
+        // the temporary file mustn't be the log itself
+        let path = temp_path("wal.compact");
+        {
+            let mut map = DurableMap::open(&path).unwrap();
+            map.insert(1u64, 1u64).unwrap();
+            map.insert(1, 2).unwrap();
+            map.compact().unwrap();
+            map.insert(2, 3).unwrap();
+        }
+        let map = DurableMap::<u64, u64>::open(&path).unwrap();
+        assert_eq!(map.get(&1), Some(&2));
+        assert_eq!(map.get(&2), Some(&3));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn failed_compaction() {
+        let path = temp_path("wal-failed-compaction");
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".compacting");
+        // the temporary file can't be created over a directory
+        fs::create_dir(&tmp).unwrap();
+        {
+            let mut map = DurableMap::open(&path).unwrap();
+            map.set_compaction_threshold(2);
+            for i in 0..4u64 {
+                assert_eq!(map.insert(1u64, i).unwrap(), i.checked_sub(1));
+            }
+            assert_eq!(map.records, 4);
+            assert!(map.sync().is_err());
+            map.sync().unwrap();
+            assert!(map.compact().is_err());
+            map.insert(2, 5).unwrap();
+        }
+        fs::remove_dir(&tmp).unwrap();
+        let mut map = DurableMap::<u64, u64>::open(&path).unwrap();
+        assert_eq!(map.get(&1), Some(&3));
+        assert_eq!(map.get(&2), Some(&5));
+        map.compact().unwrap();
+        assert_eq!(map.records, 2);
+        fs::remove_file(&path).unwrap();
+    }
+}
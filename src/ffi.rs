@@ -0,0 +1,144 @@
+//! C bindings over a byte-string `Hashmap`
+//!
+//! The map is handed out as an opaque pointer, see `include/hashmap.h`.
+//! Build a linkable library with e.g.
+//! `cargo rustc --release --features ffi --crate-type staticlib`.
+use std::{ffi::c_int, ptr, slice};
+
+use crate::Hashmap;
+
+pub struct HashmapHandle {
+    map: Hashmap<Vec<u8>, Vec<u8>>,
+}
+
+/// `len` bytes at `ptr`, which may be null when `len` is 0
+unsafe fn bytes<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(ptr, len)
+    }
+}
+
+/// Allocates an empty map, release it with `hashmap_free`.
+#[no_mangle]
+pub extern "C" fn hashmap_new() -> *mut HashmapHandle {
+    Box::into_raw(Box::new(HashmapHandle {
+        map: Hashmap::new(),
+    }))
+}
+
+/// # Safety
+///
+/// `map` must come from `hashmap_new` and not be used afterwards; null is
+/// a no-op.
+#[no_mangle]
+pub unsafe extern "C" fn hashmap_free(map: *mut HashmapHandle) {
+    if !map.is_null() {
+        drop(Box::from_raw(map));
+    }
+}
+
+/// Copies key and value into the map. Returns 1 if an existing value was
+/// replaced, 0 if the key was new and -1 if `map` is null.
+///
+/// # Safety
+///
+/// `map` must be a live handle, `key`/`value` must point at
+/// `key_len`/`value_len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn hashmap_insert(
+    map: *mut HashmapHandle,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) -> c_int {
+    let Some(handle) = map.as_mut() else {
+        return -1;
+    };
+    let key = bytes(key, key_len).to_vec();
+    let value = bytes(value, value_len).to_vec();
+    handle.map.insert(key, value).is_some() as c_int
+}
+
+/// Looks `key` up. Returns a pointer to the value and stores its length in
+/// `value_len`, or returns null if the key is missing. The pointer stays
+/// valid until the map is next modified or freed.
+///
+/// # Safety
+///
+/// `map` must be a live handle, `key` must point at `key_len` readable
+/// bytes and `value_len` must be writable (or null).
+#[no_mangle]
+pub unsafe extern "C" fn hashmap_get(
+    map: *const HashmapHandle,
+    key: *const u8,
+    key_len: usize,
+    value_len: *mut usize,
+) -> *const u8 {
+    let Some(handle) = map.as_ref() else {
+        return ptr::null();
+    };
+    match handle.map.get(bytes(key, key_len)) {
+        Some(value) => {
+            if !value_len.is_null() {
+                *value_len = value.len();
+            }
+            value.as_ptr()
+        }
+        None => ptr::null(),
+    }
+}
+
+/// Removes `key`. Returns 1 if it was present, 0 if not and -1 if `map` is
+/// null.
+///
+/// # Safety
+///
+/// `map` must be a live handle and `key` must point at `key_len` readable
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn hashmap_remove(
+    map: *mut HashmapHandle,
+    key: *const u8,
+    key_len: usize,
+) -> c_int {
+    let Some(handle) = map.as_mut() else {
+        return -1;
+    };
+    handle.map.remove(bytes(key, key_len)).is_some() as c_int
+}
+
+/// # Safety
+///
+/// `map` must be a live handle or null (which counts as empty).
+#[no_mangle]
+pub unsafe extern "C" fn hashmap_len(map: *const HashmapHandle) -> usize {
+    map.as_ref().map_or(0, |handle| handle.map.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        unsafe {
+            let map = hashmap_new();
+            assert_eq!(hashmap_insert(map, b"foo".as_ptr(), 3, b"bar".as_ptr(), 3), 0);
+            assert_eq!(hashmap_insert(map, b"foo".as_ptr(), 3, b"baz!".as_ptr(), 4), 1);
+            assert_eq!(hashmap_len(map), 1);
+
+            let mut len = 0;
+            let value = hashmap_get(map, b"foo".as_ptr(), 3, &mut len);
+            assert_eq!(slice::from_raw_parts(value, len), b"baz!");
+            assert!(hashmap_get(map, ptr::null(), 0, &mut len).is_null());
+
+            assert_eq!(hashmap_remove(map, b"foo".as_ptr(), 3), 1);
+            assert_eq!(hashmap_remove(map, b"foo".as_ptr(), 3), 0);
+            hashmap_free(map);
+            assert_eq!(hashmap_insert(ptr::null_mut(), ptr::null(), 0, ptr::null(), 0), -1);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+//! when a `Hashmap` grows, and to how many buckets
+//!
+//! By default the table doubles its buckets once the load factor passes
+//! 3/4. A `GrowthPolicy` replaces that rule, e.g. to cap the bucket
+//! array on an embedded target: past the cap, chains just get longer.
+//! Bucket counts are always powers of two.
+
+/// Decides when a table needs more buckets and how many.
+pub trait GrowthPolicy: Send + Sync {
+    /// whether `len` entries are too many for `buckets` buckets; a table
+    /// without buckets grows to its first one without asking
+    fn is_overloaded(&self, len: usize, buckets: usize) -> bool;
+
+    /// the bucket count to grow to from `buckets` (never 0): a larger
+    /// power of two, or `None` to stay at `buckets`
+    fn grow(&self, buckets: usize) -> Option<usize>;
+}
+
+/// The default policy, with its two knobs exposed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Doubling {
+    /// entries per bucket to grow past
+    pub max_load_factor: f32,
+    /// never grows past this many buckets
+    pub max_buckets: usize,
+}
+
+impl Default for Doubling {
+    fn default() -> Self {
+        Doubling {
+            max_load_factor: 0.75,
+            max_buckets: usize::MAX,
+        }
+    }
+}
+
+impl GrowthPolicy for Doubling {
+    fn is_overloaded(&self, len: usize, buckets: usize) -> bool {
+        // the same threshold as the built-in rule
+        buckets == 0 || len > (buckets as f64 * self.max_load_factor as f64) as usize + 1
+    }
+
+    fn grow(&self, buckets: usize) -> Option<usize> {
+        let next = if buckets == 0 { 1 } else { buckets.checked_mul(2)? };
+        (next <= self.max_buckets).then_some(next)
+    }
+}
@@ -0,0 +1,294 @@
+//! a map that drops its fast hasher when keys start colliding
+//!
+//! A fast unkeyed hasher (like `DefaultHashBuilder`) is predictable, so
+//! whoever picks the keys can pile them all into one chain and make every
+//! lookup a linear scan. `HardenedMap` hashes with the fast hasher until
+//! an insert lands in a chain longer than `MAX_CHAIN_LEN`, which chance
+//! alone practically never produces at the maximum load factor, then
+//! rehashes everything once with a randomly seeded SipHash
+//! (`RandomState`) and keeps that from then on.
+//!
+//! To hear about it, give the map a `CollisionHook`: it is called with the
+//! new key whenever an insert lands in a chain longer than a threshold of
+//! your choosing, e.g. to log a suspected HashDoS before (or instead of)
+//! the map reseeding.
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, DefaultHasher, Hash, Hasher, RandomState},
+    mem,
+};
+
+use crate::{make_hash, DefaultHashBuilder, Hashmap};
+
+/// the longest chain an insert tolerates before reseeding
+pub const MAX_CHAIN_LEN: usize = 24;
+
+#[derive(Clone)]
+enum State<S> {
+    Fast(S),
+    Seeded(RandomState),
+}
+
+enum StateHasher<H> {
+    Fast(H),
+    Seeded(DefaultHasher),
+}
+
+impl<S: BuildHasher> BuildHasher for State<S> {
+    type Hasher = StateHasher<S::Hasher>;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        match self {
+            State::Fast(fast) => StateHasher::Fast(fast.build_hasher()),
+            State::Seeded(seeded) => StateHasher::Seeded(seeded.build_hasher()),
+        }
+    }
+}
+
+impl<H: Hasher> Hasher for StateHasher<H> {
+    fn write(&mut self, bytes: &[u8]) {
+        match self {
+            StateHasher::Fast(h) => h.write(bytes),
+            StateHasher::Seeded(h) => h.write(bytes),
+        }
+    }
+
+    // forwarded too, for fast hashers that special-case integers
+    fn write_u64(&mut self, i: u64) {
+        match self {
+            StateHasher::Fast(h) => h.write_u64(i),
+            StateHasher::Seeded(h) => h.write_u64(i),
+        }
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        match self {
+            StateHasher::Fast(h) => h.write_usize(i),
+            StateHasher::Seeded(h) => h.write_usize(i),
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        match self {
+            StateHasher::Fast(h) => h.finish(),
+            StateHasher::Seeded(h) => h.finish(),
+        }
+    }
+}
+
+/// Told about inserts that land in long chains, see `HardenedMap`.
+///
+/// Implemented by closures taking the key and the chain length.
+pub trait CollisionHook<K> {
+    fn long_chain(&mut self, key: &K, chain_len: usize);
+}
+
+impl<K, F: FnMut(&K, usize)> CollisionHook<K> for F {
+    fn long_chain(&mut self, key: &K, chain_len: usize) {
+        self(key, chain_len)
+    }
+}
+
+/// The hook of a `HardenedMap` without one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoHook;
+
+impl<K> CollisionHook<K> for NoHook {
+    fn long_chain(&mut self, _: &K, _: usize) {}
+}
+
+// the hook is a type parameter rather than a boxed `dyn FnMut(&K, usize)`,
+// which would make the map invariant in `K`
+#[derive(Clone)]
+pub struct HardenedMap<K, V, S = DefaultHashBuilder, H = NoHook> {
+    map: Hashmap<K, V, State<S>>,
+    hook: H,
+    // chains longer than this call the hook
+    alert_len: usize,
+}
+
+impl<K, V> HardenedMap<K, V> {
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, H> HardenedMap<K, V, DefaultHashBuilder, H> {
+    /// A map that calls `hook` for every insert into a chain longer than
+    /// `alert_len`. With `alert_len` below `MAX_CHAIN_LEN` the hook hears
+    /// about a flood before the map reseeds.
+    pub fn with_collision_hook(alert_len: usize, hook: H) -> Self {
+        Self::with_hasher_and_collision_hook(DefaultHashBuilder::default(), alert_len, hook)
+    }
+}
+
+impl<K, V> Default for HardenedMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, S> HardenedMap<K, V, S> {
+    /// a map that hashes with `hash_builder` until keys collide too much
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self::with_hasher_and_collision_hook(hash_builder, usize::MAX, NoHook)
+    }
+}
+
+impl<K, V, S, H> HardenedMap<K, V, S, H> {
+    pub fn with_hasher_and_collision_hook(hash_builder: S, alert_len: usize, hook: H) -> Self {
+        HardenedMap {
+            map: Hashmap::with_hasher(State::Fast(hash_builder)),
+            hook,
+            alert_len,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// whether the map gave up on its own hasher for a seeded one
+    pub fn is_reseeded(&self) -> bool {
+        matches!(self.map.hash_builder, State::Seeded(_))
+    }
+
+    pub fn iter(&self) -> crate::Iter<'_, K, V> {
+        (&self.map).into_iter()
+    }
+}
+
+impl<K, V, S, H> HardenedMap<K, V, S, H>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+    H: CollisionHook<K>,
+{
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = make_hash(&self.map.hash_builder, &key);
+        let (slot, pending) = self.map.insert_slot_hashed(hash, key, value);
+        if let Some((_, value)) = pending {
+            return Some(mem::replace(&mut self.map.table.slot_mut(slot).1, value));
+        }
+        let chain_len = self.map.table.chain_len(hash);
+        if chain_len > self.alert_len {
+            self.hook.long_chain(&self.map.table.slot(slot).0, chain_len);
+        }
+        if !self.is_reseeded() && chain_len > MAX_CHAIN_LEN {
+            self.reseed();
+        }
+        None
+    }
+
+    fn reseed(&mut self) {
+        let entries = mem::take(&mut self.map.table);
+        self.map.hash_builder = State::Seeded(RandomState::new());
+        self.map.table.reserve(entries.len());
+        for (key, value) in entries {
+            let hash = make_hash(&self.map.hash_builder, &key);
+            // the keys are already unique
+            self.map.table.insert_no_grow(hash, (key, value));
+        }
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.get(key)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.get_mut(key)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.remove(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::BuildHasherDefault;
+
+    use super::*;
+
+    /// every key in one chain
+    #[derive(Default)]
+    struct Constant;
+
+    impl Hasher for Constant {
+        fn write(&mut self, _: &[u8]) {}
+
+        fn finish(&self) -> u64 {
+            0
+        }
+    }
+
+    #[test]
+    fn reseeds_on_collisions() {
+        let mut map = HardenedMap::with_hasher(BuildHasherDefault::<Constant>::default());
+        for i in 0..=MAX_CHAIN_LEN as u32 {
+            map.insert(i, i);
+        }
+        assert!(map.is_reseeded());
+        for i in 0..1_000 {
+            map.insert(i, i * 2);
+        }
+        assert_eq!(map.len(), 1_000);
+        assert_eq!(map.get(&7), Some(&14));
+        assert_eq!(map.remove(&8), Some(16));
+        assert!(map.map.stats().max_chain_len <= MAX_CHAIN_LEN);
+        assert!(map.map.debug_validate().is_ok());
+    }
+
+    #[test]
+    fn collision_hook() {
+        let mut alerts = Vec::new();
+        let mut map = HardenedMap::with_hasher_and_collision_hook(
+            BuildHasherDefault::<Constant>::default(),
+            4,
+            |key: &u32, chain_len: usize| alerts.push((*key, chain_len)),
+        );
+        for i in 0..100 {
+            map.insert(i, ());
+        }
+        // every insert into the one chain past 4, up to the reseed
+        assert!(map.is_reseeded());
+        drop(map);
+        assert_eq!(alerts.first(), Some(&(4, 5)));
+        assert!(alerts.contains(&(MAX_CHAIN_LEN as u32, MAX_CHAIN_LEN + 1)));
+        assert!(alerts.iter().all(|&(_, len)| len <= MAX_CHAIN_LEN + 1));
+    }
+
+    #[test]
+    fn keeps_a_good_hasher() {
+        let mut map = HardenedMap::new();
+        for i in 0..100_000u64 {
+            map.insert(i, ());
+        }
+        assert!(!map.is_reseeded());
+        assert!(map.contains_key(&99_999));
+    }
+}
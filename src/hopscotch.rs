@@ -0,0 +1,399 @@
+//! a hopscotch-hashing table
+//!
+//! Every element sits within `NEIGHBORHOOD` slots of its home slot, and
+//! each home slot keeps a bitmap of which of those slots hold its
+//! elements, so a lookup reads one bitmap and then only the matching
+//! slots, all close together in memory. Inserting takes the nearest free
+//! slot; if that's too far from home, elements in between hop towards it
+//! until the free slot is close enough. When no element can hop, the
+//! element goes to a small overflow list instead; the table grows once
+//! that fills up.
+//!
+//! `HopscotchMap` is a `Hashmap` on `HopscotchTable`.
+use std::{
+    iter::{Chain, Flatten},
+    mem, slice,
+};
+
+use crate::TableBackend;
+
+const INITIAL_CAPACITY: usize = 32;
+/// how far from its home slot an element may be; one bit each in `hops`
+pub const NEIGHBORHOOD: usize = 32;
+const MAX_OVERFLOW: usize = 4;
+
+/// Position of an element inside a `HopscotchTable`.
+///
+/// A slot stays valid until the table is modified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Slot {
+    // past the end of `slots` for elements in the overflow list
+    index: usize,
+}
+
+#[derive(Clone)]
+struct Bucket<T> {
+    hash: u64,
+    value: T,
+}
+
+type Slots<'a, T> = Flatten<slice::Iter<'a, Option<Bucket<T>>>>;
+
+/// Iterator over a `HopscotchTable`'s elements, from `HopscotchTable::iter`.
+pub struct Iter<'a, T> {
+    inner: Chain<Slots<'a, T>, slice::Iter<'a, Bucket<T>>>,
+}
+
+#[derive(Clone)]
+pub struct HopscotchTable<T> {
+    // a power of two long (at least NEIGHBORHOOD), or empty
+    slots: Vec<Option<Bucket<T>>>,
+    // bit `d` of `hops[i]` is set when slot `i + d` holds an element whose
+    // home slot is `i`
+    hops: Vec<u32>,
+    overflow: Vec<Bucket<T>>,
+    // overflow size that triggers growing
+    overflow_limit: usize,
+    items: usize,
+}
+
+impl<T> HopscotchTable<T> {
+    pub const fn new() -> Self {
+        HopscotchTable {
+            slots: Vec::new(),
+            hops: Vec::new(),
+            overflow: Vec::new(),
+            overflow_limit: MAX_OVERFLOW,
+            items: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+
+    /// number of slots, always zero or a power of two
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// elements that found no place in their neighborhood
+    pub fn overflow_len(&self) -> usize {
+        self.overflow.len()
+    }
+
+    fn mask(&self) -> usize {
+        self.slots.len() - 1
+    }
+
+    fn home(&self, hash: u64) -> usize {
+        hash as usize & self.mask()
+    }
+
+    /// how far slot `index` is past slot `from`
+    fn distance(&self, from: usize, index: usize) -> usize {
+        index.wrapping_sub(from) & self.mask()
+    }
+
+    pub fn find(&self, hash: u64, eq: impl FnMut(&T) -> bool) -> Option<Slot> {
+        self.find_probed(hash, eq).0
+    }
+
+    /// `find`, also counting the neighborhood and overflow elements it
+    /// looked at
+    pub fn find_probed(&self, hash: u64, mut eq: impl FnMut(&T) -> bool) -> (Option<Slot>, usize) {
+        if self.slots.is_empty() {
+            return (None, 0);
+        }
+        let home = self.home(hash);
+        let mut hops = self.hops[home];
+        let mut probes = 0;
+        while hops != 0 {
+            let index = (home + hops.trailing_zeros() as usize) & self.mask();
+            hops &= hops - 1;
+            let bucket = self.slots[index].as_ref().unwrap();
+            probes += 1;
+            if bucket.hash == hash && eq(&bucket.value) {
+                return (Some(Slot { index }), probes);
+            }
+        }
+        let overflowed = self.overflow.iter().position(|bucket| {
+            probes += 1;
+            bucket.hash == hash && eq(&bucket.value)
+        });
+        let slot = overflowed.map(|overflowed| Slot {
+            index: self.slots.len() + overflowed,
+        });
+        (slot, probes)
+    }
+
+    /// # Panics
+    ///
+    /// if `slot` doesn't point at an element (e.g. it is stale)
+    pub fn slot(&self, slot: Slot) -> &T {
+        let bucket = match slot.index.checked_sub(self.slots.len()) {
+            Some(overflowed) => &self.overflow[overflowed],
+            None => self.slots[slot.index].as_ref().expect("stale slot"),
+        };
+        &bucket.value
+    }
+
+    /// # Panics
+    ///
+    /// if `slot` doesn't point at an element (e.g. it is stale)
+    pub fn slot_mut(&mut self, slot: Slot) -> &mut T {
+        let bucket = match slot.index.checked_sub(self.slots.len()) {
+            Some(overflowed) => &mut self.overflow[overflowed],
+            None => self.slots[slot.index].as_mut().expect("stale slot"),
+        };
+        &mut bucket.value
+    }
+
+    /// Makes room for `additional` more elements, growing if the load
+    /// factor would go above 7/8 or the overflow list is full.
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = self.items + additional;
+        if needed <= self.slots.len() / 8 * 7 && self.overflow.len() < self.overflow_limit {
+            return;
+        }
+        let mut capacity = self.slots.len().max(INITIAL_CAPACITY);
+        while needed > capacity / 8 * 7 {
+            capacity *= 2;
+        }
+        if capacity == self.slots.len() {
+            capacity *= 2;
+        }
+        self.resize(capacity);
+    }
+
+    fn resize(&mut self, capacity: usize) {
+        let old = mem::replace(&mut self.slots, (0..capacity).map(|_| None).collect());
+        let overflow = mem::take(&mut self.overflow);
+        self.hops = vec![0; capacity];
+        self.items = 0;
+        for bucket in old.into_iter().flatten().chain(overflow) {
+            self.insert_no_grow(bucket.hash, bucket.value);
+        }
+        // more than NEIGHBORHOOD elements with the same home overflow
+        // however big the table gets; don't grow again just for them
+        self.overflow_limit = MAX_OVERFLOW.max(self.overflow.len() * 2);
+    }
+
+    /// Inserts `value` under `hash` without checking for an equal element;
+    /// callers wanting map semantics must `find` first.
+    pub fn insert(&mut self, hash: u64, value: T) -> Slot {
+        self.reserve(1);
+        self.insert_no_grow(hash, value)
+    }
+
+    /// Like `insert`, for callers that already called `reserve`; may put
+    /// `value` into the overflow list.
+    ///
+    /// # Panics
+    ///
+    /// if the table is full
+    pub fn insert_no_grow(&mut self, hash: u64, value: T) -> Slot {
+        assert!(self.items < self.slots.len(), "HopscotchTable is full");
+        self.items += 1;
+        let bucket = Bucket { hash, value };
+        match self.free_slot_near(self.home(hash)) {
+            Some(index) => {
+                let home = self.home(hash);
+                self.place(home, index, bucket);
+                Slot { index }
+            }
+            None => {
+                self.overflow.push(bucket);
+                Slot {
+                    index: self.slots.len() + self.overflow.len() - 1,
+                }
+            }
+        }
+    }
+
+    fn place(&mut self, home: usize, index: usize, bucket: Bucket<T>) {
+        self.hops[home] |= 1 << self.distance(home, index);
+        self.slots[index] = Some(bucket);
+    }
+
+    /// Finds the nearest free slot after `home` and hops elements towards
+    /// it until it's within `home`'s neighborhood, if they can.
+    fn free_slot_near(&mut self, home: usize) -> Option<usize> {
+        let mut free = (0..self.slots.len())
+            .map(|d| (home + d) & self.mask())
+            .find(|&index| self.slots[index].is_none())?;
+        while self.distance(home, free) >= NEIGHBORHOOD {
+            // the farthest-back element that may move to `free`, freeing
+            // its own slot
+            let hop = (1..NEIGHBORHOOD).rev().find_map(|back| {
+                let index = free.wrapping_sub(back) & self.mask();
+                let its_home = self.home(self.slots[index].as_ref()?.hash);
+                (self.distance(its_home, free) < NEIGHBORHOOD).then_some((index, its_home))
+            });
+            let (index, its_home) = hop?;
+            let bucket = self.slots[index].take().unwrap();
+            self.hops[its_home] &= !(1 << self.distance(its_home, index));
+            self.place(its_home, free, bucket);
+            free = index;
+        }
+        Some(free)
+    }
+
+    /// Removes the element at `slot`, moving an overflowed element into
+    /// the freed slot if it's in its neighborhood; that invalidates
+    /// overflow slots.
+    pub fn erase(&mut self, slot: Slot) -> T {
+        self.items -= 1;
+        if let Some(overflowed) = slot.index.checked_sub(self.slots.len()) {
+            return self.overflow.swap_remove(overflowed).value;
+        }
+        let removed = self.slots[slot.index].take().expect("stale slot");
+        let home = self.home(removed.hash);
+        self.hops[home] &= !(1 << self.distance(home, slot.index));
+        let fits = self.overflow.iter().position(|bucket| {
+            self.distance(self.home(bucket.hash), slot.index) < NEIGHBORHOOD
+        });
+        if let Some(overflowed) = fits {
+            let bucket = self.overflow.swap_remove(overflowed);
+            let home = self.home(bucket.hash);
+            self.place(home, slot.index, bucket);
+        }
+        removed.value
+    }
+
+    pub fn remove(&mut self, hash: u64, eq: impl FnMut(&T) -> bool) -> Option<T> {
+        let slot = self.find(hash, eq)?;
+        Some(self.erase(slot))
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            inner: self.slots.iter().flatten().chain(&self.overflow),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> + '_ {
+        self.slots
+            .iter_mut()
+            .flatten()
+            .chain(&mut self.overflow)
+            .map(|bucket| &mut bucket.value)
+    }
+}
+
+impl<T> Default for HopscotchTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        self.inner.next().map(|bucket| &bucket.value)
+    }
+}
+
+impl<K, V> TableBackend<K, V> for HopscotchTable<(K, V)> {
+    type Slot = Slot;
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn bucket_count(&self) -> usize {
+        self.capacity()
+    }
+
+    fn find(&self, hash: u64, eq: impl FnMut(&(K, V)) -> bool) -> Option<Slot> {
+        self.find(hash, eq)
+    }
+
+    fn find_probed(&self, hash: u64, eq: impl FnMut(&(K, V)) -> bool) -> (Option<Slot>, usize) {
+        self.find_probed(hash, eq)
+    }
+
+    fn slot(&self, slot: Slot) -> &(K, V) {
+        self.slot(slot)
+    }
+
+    fn slot_mut(&mut self, slot: Slot) -> &mut (K, V) {
+        self.slot_mut(slot)
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.reserve(additional)
+    }
+
+    fn insert_no_grow(&mut self, hash: u64, entry: (K, V)) -> Slot {
+        self.insert_no_grow(hash, entry)
+    }
+
+    fn erase(&mut self, slot: Slot) -> (K, V) {
+        self.erase(slot)
+    }
+
+    fn resize(&mut self) {
+        self.resize((self.capacity() * 2).max(INITIAL_CAPACITY))
+    }
+
+    fn next_run(&self, pos: &mut usize) -> &[(K, V)] {
+        // the slots, then the overflow
+        for (index, bucket) in self.slots.iter().enumerate().skip(*pos) {
+            if let Some(bucket) = bucket {
+                *pos = index + 1;
+                return std::slice::from_ref(&bucket.value);
+            }
+        }
+        *pos = (*pos).max(self.slots.len());
+        let Some(bucket) = self.overflow.get(*pos - self.slots.len()) else {
+            return &[];
+        };
+        *pos += 1;
+        std::slice::from_ref(&bucket.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table() {
+        let mut table = HopscotchTable::new();
+        table.reserve(200);
+        // a crowded run of homes, so elements have to hop
+        for v in 0..200u64 {
+            let slot = table.insert(v % 40, v);
+            assert_eq!(*table.slot(slot), v);
+        }
+        for v in 0..200u64 {
+            assert_eq!(table.find(v % 40, |&e| e == v).map(|s| *table.slot(s)), Some(v));
+        }
+        for v in (0..200u64).step_by(2) {
+            assert_eq!(table.remove(v % 40, |&e| e == v), Some(v));
+        }
+        assert_eq!(table.len(), 100);
+        assert_eq!(table.iter().count(), 100);
+    }
+
+    #[test]
+    fn map() {
+        let mut map: crate::HopscotchMap<_, _> = crate::HopscotchMap::default();
+        for i in 0..10_000u32 {
+            assert_eq!(map.insert(i, i * 2), None);
+        }
+        assert_eq!(map.insert(7, 0), Some(14));
+        for i in (0..10_000).step_by(2) {
+            assert!(map.remove(&i).is_some());
+        }
+        assert_eq!(map.len(), 5_000);
+        assert_eq!(map.get(&7), Some(&0));
+        assert_eq!(map.get(&8), None);
+        assert_eq!(map.table.overflow_len(), 0);
+    }
+}
@@ -1,23 +1,122 @@
 //! this module implements a linked hashmap
 use std::{
-    borrow::{Borrow}, hash::{DefaultHasher, Hash, Hasher}, mem
+    borrow::Borrow, cmp::Ordering, collections::TryReserveError, error::Error, fmt, hash::{BuildHasher, BuildHasherDefault, DefaultHasher, Hash}, marker::PhantomData, mem, ops::AddAssign, sync::Arc
 };
-const INITIAL_NBUCKET: usize = 1;
 
-pub struct Hashmap<K, V> {
-    buckets: Vec<Vec<(K, V)>>,
-    items: usize,
+#[macro_use]
+mod macros;
+
+#[cfg(feature = "raw")]
+pub mod raw;
+#[cfg(not(feature = "raw"))]
+#[allow(dead_code)]
+mod raw;
+
+#[cfg(all(feature = "mmap", unix))]
+pub mod mmap;
+#[cfg(all(feature = "mmap", unix))]
+pub use mmap::MmapMap;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+pub mod codec;
+pub mod concurrent;
+pub use concurrent::ConcurrentHashmap;
+pub mod atomic;
+pub use atomic::AtomicCounterMap;
+pub mod rcu;
+pub use rcu::RcuMap;
+#[cfg(feature = "async")]
+mod async_lock;
+#[cfg(feature = "async")]
+pub mod async_map;
+#[cfg(feature = "async")]
+pub use async_map::AsyncConcurrentHashmap;
+pub mod durable;
+pub use durable::DurableMap;
+mod snapshot;
+mod bulk;
+pub use bulk::{LengthMismatch, Op};
+pub mod stats;
+pub mod metrics;
+pub use metrics::MetricsSink;
+pub mod observe;
+pub use observe::ObservedMap;
+pub mod diff;
+pub use diff::{MapDiff, MapPatch};
+pub mod backend;
+pub use backend::{CuckooMap, HopscotchMap, RobinHoodMap, SwissMap, TableBackend};
+pub mod robin_hood;
+pub mod swiss;
+pub mod cuckoo;
+pub mod hopscotch;
+pub mod small;
+pub use small::{AutoMap, SmallMap};
+pub mod hardened;
+pub use hardened::{CollisionHook, HardenedMap, NoHook};
+pub mod strategy;
+pub use strategy::{KeyStrategy, StrategyMap};
+pub mod growth;
+pub use growth::GrowthPolicy;
+pub mod transaction;
+pub use transaction::Transaction;
+pub mod versioned;
+pub use versioned::VersionedMap;
+pub mod shared;
+pub use shared::SharedHasher;
+pub mod default_map;
+pub use default_map::DefaultHashmap;
+pub use stats::{BucketReport, Stats};
+
+use raw::RawTable;
+pub use raw::Violation;
+
+/// The hasher maps use unless told otherwise: SipHash with fixed keys.
+///
+/// It never asks the OS for randomness (so it works as-is on targets like
+/// `wasm32-unknown-unknown`) and hashes identically across maps and runs.
+/// Use `Hashmap::with_hasher(RandomState::new())` for keys controlled by
+/// an attacker.
+pub type DefaultHashBuilder = BuildHasherDefault<DefaultHasher>;
+
+/// A hash map, on the chained `RawTable` unless another `TableBackend`
+/// is picked.
+///
+/// On the default backend, iteration order is part of the API and
+/// doesn't depend on the hasher or its seed: entries come in insertion
+/// order, except that removing one moves the last entry into its place.
+/// Growing, shrinking and rehashing keep the order. The same sequence of
+/// calls always iterates the same way, which golden-file tests can rely
+/// on. The open-addressing backends iterate in slot order, which does
+/// depend on the hashes.
+#[derive(Clone)]
+pub struct Hashmap<K, V, S = DefaultHashBuilder, B = RawTable<(K, V)>> {
+    table: B,
+    hash_builder: S,
+    // clones share the sink
+    metrics: Option<Arc<dyn MetricsSink>>,
+    // for backends other than `RawTable`, which don't name `K` and `V`
+    marker: PhantomData<(K, V)>,
+}
+
+pub struct Iter<'a, K, V, B = RawTable<(K, V)>> {
+    table: &'a B,
+    // the run being walked, and where the next one starts
+    run: std::slice::Iter<'a, (K, V)>,
+    pos: usize,
+    remaining: usize,
+    #[cfg(debug_assertions)]
+    generation: u64,
 }
 
-pub struct Iter<'a, K, V> {
-    map: &'a Hashmap<K, V>,
-    current_bucket: usize,
-    current_item: usize,
+pub struct IntoIter<K, V> {
+    inner: raw::RawIntoIter<(K, V)>,
 }
 
-pub enum Entry<'a, K, V> {
+pub enum Entry<'a, K, V, B = RawTable<(K, V)>> {
     Occupied(OccupiedEntry<'a, K, V>),
-    Vacant(VacantEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V, B>),
 }
 
 #[allow(dead_code)]
@@ -25,23 +124,37 @@ pub struct OccupiedEntry<'a, K, V> {
     element: &'a mut (K, V),
 }
 
-#[allow(dead_code)]
-pub struct VacantEntry<'a, K, V> {
-    bucket: &'a mut Vec<(K, V)>,
+pub struct VacantEntry<'a, K, V, B = RawTable<(K, V)>> {
+    table: &'a mut B,
+    metrics: Option<&'a dyn MetricsSink>,
+    hash: u64,
     key: K,
+    marker: PhantomData<V>,
 }
 
-impl<'a, K, V> VacantEntry<'a, K, V> {
+impl<'a, K: 'a, V: 'a, B: TableBackend<K, V>> VacantEntry<'a, K, V, B> {
     pub fn insert(self, default: V) -> &'a mut V {
-        self.bucket.push((self.key, default));
-        // unwrap in this case is safe because we've just pushed the element
-        // we know, it's there!
-        &mut self.bucket.last_mut().unwrap().1
+        &mut self.insert_entry(default).1
+    }
+
+    fn insert_entry(self, value: V) -> &'a mut (K, V) {
+        // growing moves entries around but the hash still says where the
+        // new one goes, so there's no second lookup
+        let old_buckets = self.table.bucket_count();
+        self.table.reserve(1);
+        if let Some(metrics) = self.metrics {
+            if self.table.bucket_count() != old_buckets {
+                metrics.record_resize(old_buckets, self.table.bucket_count());
+            }
+            record_new_key(metrics, self.table, self.hash);
+        }
+        let slot = self.table.insert_no_grow(self.hash, (self.key, value));
+        self.table.slot_mut(slot)
     }
 }
 
 
-impl<'a, K, V> Entry<'a, K, V> {
+impl<'a, K: 'a, V: 'a, B: TableBackend<K, V>> Entry<'a, K, V, B> {
     pub fn or_insert(self, default: V) -> &'a mut V {
         match self {
             Entry::Occupied(e) => &mut e.element.1,
@@ -58,45 +171,63 @@ impl<'a, K, V> Entry<'a, K, V> {
             Entry::Vacant(e) => e.insert(V::default()),
         }
     }
+
+    /// Like `or_insert`, with a value that may fail to come into being;
+    /// on `Err` nothing is inserted and the error is handed back.
+    pub fn or_try_insert_with<E>(self, default: impl FnOnce() -> Result<V, E>) -> Result<&'a mut V, E> {
+        match self {
+            Entry::Occupied(e) => Ok(&mut e.element.1),
+            Entry::Vacant(e) => Ok(e.insert(default()?)),
+        }
+    }
 }
 
 
-impl<'a, K, V> Iter<'a, K, V> {
-    fn new(map: &'a Hashmap<K, V>) -> Self{
+impl<'a, K: 'a, V: 'a, B: TableBackend<K, V>> Iter<'a, K, V, B> {
+    fn new<S>(map: &'a Hashmap<K, V, S, B>) -> Self{
         Iter {
-            map,
-            current_bucket : 0,
-            current_item : 0,
+            table: &map.table,
+            run: [].iter(),
+            pos: 0,
+            remaining: map.table.len(),
+            #[cfg(debug_assertions)]
+            generation: map.table.generation(),
         }
     }
 }
 
 
-impl<'a, K, V> Iterator for Iter<'a, K, V> {
+impl<'a, K: 'a, V: 'a, B: TableBackend<K, V>> Iterator for Iter<'a, K, V, B> {
     type Item = (&'a K, &'a V);
     fn next(&mut self) -> Option<Self::Item> {
+        #[cfg(debug_assertions)]
+        assert_eq!(
+            self.generation,
+            self.table.generation(),
+            "map modified while being iterated"
+        );
         loop {
-            match self.map.buckets.get(self.current_bucket) {
-                Some(bucket) => match bucket.get(self.current_item) {
-                    Some(&(ref key, ref val)) => {
-                        self.current_item += 1;
-                        break Some((key, val));
-                    }
-                    None => {
-                        self.current_bucket += 1;
-                        self.current_item = 0;
-                        continue;
-                    }
-                },
-                None => break None,
+            if let Some((key, val)) = self.run.next() {
+                self.remaining -= 1;
+                return Some((key, val));
+            }
+            let run = self.table.next_run(&mut self.pos);
+            if run.is_empty() {
+                return None;
             }
+            self.run = run.iter();
         }
+    }
 
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
     }
 }
 
-impl<'a, K, V> IntoIterator for &'a Hashmap<K, V> {
-    type IntoIter = Iter<'a, K, V>;
+impl<'a, K: 'a, V: 'a, B: TableBackend<K, V>> ExactSizeIterator for Iter<'a, K, V, B> {}
+
+impl<'a, K: 'a, V: 'a, S, B: TableBackend<K, V>> IntoIterator for &'a Hashmap<K, V, S, B> {
+    type IntoIter = Iter<'a, K, V, B>;
     type Item = (&'a K, &'a V);
 
     fn into_iter(self) -> Self::IntoIter {
@@ -104,70 +235,560 @@ impl<'a, K, V> IntoIterator for &'a Hashmap<K, V> {
     }
 }
 
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V> ExactSizeIterator for IntoIter<K, V> {}
+
+impl<K, V, S> IntoIterator for Hashmap<K, V, S> {
+    type IntoIter = IntoIter<K, V>;
+    type Item = (K, V);
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            inner: self.table.into_iter(),
+        }
+    }
+}
+
 impl<K, V> Hashmap<K, V> {
     pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+
+    pub fn with_metrics(metrics: Arc<dyn MetricsSink>) -> Self {
+        Self::with_hasher_and_metrics(DefaultHashBuilder::default(), metrics)
+    }
+
+    pub fn with_max_load_factor(max_load_factor: f32) -> Self {
+        Self::with_hasher_and_max_load_factor(DefaultHashBuilder::default(), max_load_factor)
+    }
+
+    pub fn with_growth_policy(policy: impl GrowthPolicy + 'static) -> Self {
+        Self::with_hasher_and_growth_policy(DefaultHashBuilder::default(), policy)
+    }
+}
+
+impl<K, V, S> Hashmap<K, V, S> {
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self::with_backend(hash_builder, RawTable::new())
+    }
+
+    /// Reports the map's activity to `metrics`, see `MetricsSink`.
+    pub fn with_hasher_and_metrics(hash_builder: S, metrics: Arc<dyn MetricsSink>) -> Self {
         Hashmap {
-            buckets: Vec::new(),
-            items: 0,
+            metrics: Some(metrics),
+            ..Self::with_hasher(hash_builder)
+        }
+    }
+
+    /// Grows once there are more than `max_load_factor` entries per
+    /// bucket, instead of 3/4: lower trades memory for shorter chains.
+    ///
+    /// # Panics
+    ///
+    /// if `max_load_factor` isn't within `0.25..=4.0`
+    pub fn with_hasher_and_max_load_factor(hash_builder: S, max_load_factor: f32) -> Self {
+        Self::with_backend(hash_builder, RawTable::with_max_load_factor(max_load_factor))
+    }
+
+    /// Grows when and as far as `policy` says instead of doubling past
+    /// the load factor, e.g. `growth::Doubling` with a bucket cap.
+    pub fn with_hasher_and_growth_policy(hash_builder: S, policy: impl GrowthPolicy + 'static) -> Self {
+        Self::with_backend(hash_builder, RawTable::with_growth_policy(Arc::new(policy)))
+    }
+
+    /// Grows a few buckets at a time, spread over the inserts and
+    /// removals that follow, instead of relinking every entry in the
+    /// insert that crosses the load factor: no single insert pays for the
+    /// whole table. Entries still live in one `Vec`, so its doubling
+    /// still copies them (without rehashing). Turning it off finishes any
+    /// growth in progress.
+    pub fn set_incremental_resize(&mut self, incremental: bool) {
+        self.table.set_incremental(incremental);
+    }
+
+    /// Shrinks the table once most entries are gone, instead of keeping
+    /// it at its peak size; see `shrink_to_fit` for doing it by hand.
+    pub fn set_auto_shrink(&mut self, auto_shrink: bool) {
+        self.table.set_auto_shrink(auto_shrink);
+    }
+
+    /// Shrinks the table as far as the entries there are allow.
+    pub fn shrink_to_fit(&mut self) {
+        let old_buckets = self.table.bucket_count();
+        self.table.shrink_to_fit();
+        if let Some(metrics) = &self.metrics {
+            if self.table.bucket_count() != old_buckets {
+                metrics.record_resize(old_buckets, self.table.bucket_count());
+            }
+        }
+    }
+
+    /// Like `reserve`, but reports allocation failure instead of aborting.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let old_buckets = self.table.bucket_count();
+        self.table.try_reserve(additional)?;
+        if let Some(metrics) = &self.metrics {
+            if self.table.bucket_count() != old_buckets {
+                metrics.record_resize(old_buckets, self.table.bucket_count());
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `reserve`, but allocates no more than the request needs: the
+    /// smallest bucket count that fits, and entry storage for exactly
+    /// `additional` more.
+    pub fn reserve_exact(&mut self, additional: usize) {
+        let old_buckets = self.table.bucket_count();
+        self.table.reserve_exact(additional);
+        if let Some(metrics) = &self.metrics {
+            if self.table.bucket_count() != old_buckets {
+                metrics.record_resize(old_buckets, self.table.bucket_count());
+            }
+        }
+    }
+
+    /// Rehashes into exactly `buckets` buckets, e.g. to size a map for a
+    /// read-only phase; the usual policy takes over again from the next
+    /// insert.
+    ///
+    /// # Panics
+    ///
+    /// if `buckets` isn't a power of two
+    pub fn rehash_to(&mut self, buckets: usize) {
+        let old_buckets = self.table.bucket_count();
+        self.table.rehash_to(buckets);
+        if let Some(metrics) = &self.metrics {
+            if buckets != old_buckets {
+                metrics.record_resize(old_buckets, buckets);
+            }
+        }
+    }
+
+    /// Removes and returns some entry in O(1). Which one is unspecified.
+    pub fn pop(&mut self) -> Option<(K, V)> {
+        let old_buckets = self.table.bucket_count();
+        let entry = self.table.pop()?;
+        if let Some(metrics) = &self.metrics {
+            metrics.record_remove();
+        }
+        self.report_resize(old_buckets);
+        Some(entry)
+    }
+
+    /// Keeps only the entries `f` returns `true` for.
+    pub fn retain(&mut self, mut f: impl FnMut(&K, &mut V) -> bool) {
+        let old_buckets = self.table.bucket_count();
+        let metrics = self.metrics.as_deref();
+        self.table.retain(|(key, value)| {
+            let keep = f(key, value);
+            if let (false, Some(metrics)) = (keep, metrics) {
+                metrics.record_remove();
+            }
+            keep
+        });
+        self.report_resize(old_buckets);
+    }
+
+    /// `retain`, a slice at a time, for maps too big to prune in one go:
+    /// checks at most `budget` entries, resuming after the ones the last
+    /// call checked. Returns `true` when it has gone over the whole map;
+    /// the next call starts over. An entry that a `remove` between calls
+    /// moved may wait until the following pass.
+    pub fn retain_budgeted(&mut self, mut f: impl FnMut(&K, &mut V) -> bool, budget: usize) -> bool {
+        let old_buckets = self.table.bucket_count();
+        let metrics = self.metrics.as_deref();
+        let done = self.table.retain_budgeted(
+            |(key, value)| {
+                let keep = f(key, value);
+                if let (false, Some(metrics)) = (keep, metrics) {
+                    metrics.record_remove();
+                }
+                keep
+            },
+            budget,
+        );
+        self.report_resize(old_buckets);
+        done
+    }
+}
+
+impl<K, V, S> Hashmap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Like `entry`, but makes room for the key up front, failing instead
+    /// of aborting if that takes an allocation that fails. Inserting
+    /// through the returned entry then never allocates for the table.
+    pub fn try_entry(&mut self, key: K) -> Result<Entry<'_, K, V>, TryReserveError> {
+        let hash = make_hash(&self.hash_builder, &key);
+        Ok(match self.lookup(hash, &key) {
+            Some(slot) => Entry::Occupied(OccupiedEntry {
+                element: self.table.slot_mut(slot),
+            }),
+            None => {
+                self.try_reserve(1)?;
+                Entry::Vacant(VacantEntry {
+                    table: &mut self.table,
+                    metrics: self.metrics.as_deref(),
+                    hash,
+                    key,
+                    marker: PhantomData,
+                })
+            }
+        })
+    }
+
+    /// Removes every key in `keys`, returning their values in order. An
+    /// auto-shrinking table shrinks at most once, after the last removal.
+    pub fn remove_many<'a, Q>(&mut self, keys: impl IntoIterator<Item = &'a Q>) -> Vec<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized + 'a,
+    {
+        let old_buckets = self.table.bucket_count();
+        let removed = keys
+            .into_iter()
+            .map(|key| {
+                let slot = self.lookup(make_hash(&self.hash_builder, key), key)?;
+                if let Some(metrics) = &self.metrics {
+                    metrics.record_remove();
+                }
+                Some(self.table.erase_no_shrink(slot).1)
+            })
+            .collect();
+        self.table.shrink_if_sparse();
+        self.report_resize(old_buckets);
+        removed
+    }
+}
+
+impl<K, V, S, B: TableBackend<K, V>> Hashmap<K, V, S, B> {
+    /// A map on any backend, e.g. `Hashmap::with_backend(hasher,
+    /// SwissTable::new())`.
+    ///
+    /// # Panics
+    ///
+    /// if `table` isn't empty
+    pub fn with_backend(hash_builder: S, table: B) -> Self {
+        assert!(table.is_empty(), "backend must start out empty");
+        Hashmap {
+            table,
+            hash_builder,
+            metrics: None,
+            marker: PhantomData,
+        }
+    }
+
+    // reports the table having resized since it had `old_buckets`, as an
+    // auto-shrinking erase does
+    fn report_resize(&self, old_buckets: usize) {
+        if let Some(metrics) = &self.metrics {
+            if self.table.bucket_count() != old_buckets {
+                metrics.record_resize(old_buckets, self.table.bucket_count());
+            }
+        }
+    }
+
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    /// Buckets (or slots, for open addressing) the table has right now.
+    pub fn bucket_count(&self) -> usize {
+        self.table.bucket_count()
+    }
+
+    /// The `n` entries with the largest values under `cmp`, largest first.
+    ///
+    /// Only those `n` get sorted: the rest are split off by a linear-time
+    /// selection first.
+    pub fn top_n_by_value(&self, n: usize, mut cmp: impl FnMut(&V, &V) -> Ordering) -> Vec<(&K, &V)> {
+        if n == 0 {
+            return Vec::new();
+        }
+        let mut entries: Vec<(&K, &V)> = self.into_iter().collect();
+        let mut largest_first = |a: &(&K, &V), b: &(&K, &V)| cmp(b.1, a.1);
+        if n < entries.len() {
+            entries.select_nth_unstable_by(n - 1, &mut largest_first);
+            entries.truncate(n);
+        }
+        entries.sort_unstable_by(largest_first);
+        entries
+    }
+
+    /// Every entry, smallest value under `cmp` first. Entries with equal
+    /// values come in iteration order.
+    pub fn iter_sorted_by_value(&self, mut cmp: impl FnMut(&V, &V) -> Ordering) -> std::vec::IntoIter<(&K, &V)> {
+        let mut entries: Vec<(&K, &V)> = self.into_iter().collect();
+        entries.sort_by(|a, b| cmp(a.1, b.1));
+        entries.into_iter()
+    }
+
+    /// The first entry, in iteration order, that `pred` holds for.
+    pub fn find(&self, mut pred: impl FnMut(&K, &V) -> bool) -> Option<(&K, &V)> {
+        self.find_map(|key, value| pred(key, value).then_some((key, value)))
+    }
+
+    /// The first `Some` that `f` returns for an entry, in iteration order.
+    /// Stops there, walking the table directly rather than through `Iter`.
+    pub fn find_map<'a, T>(&'a self, mut f: impl FnMut(&'a K, &'a V) -> Option<T>) -> Option<T> {
+        let mut pos = 0;
+        loop {
+            let run = self.table.next_run(&mut pos);
+            if run.is_empty() {
+                return None;
+            }
+            for (key, value) in run {
+                if let Some(found) = f(key, value) {
+                    return Some(found);
+                }
+            }
         }
     }
 }
 
-impl<K, V> Default for Hashmap<K, V> {
+impl<K, V, S, B> Default for Hashmap<K, V, S, B>
+where
+    S: Default,
+    B: TableBackend<K, V>,
+{
     fn default() -> Self {
-        Self::new()
+        Self::with_backend(S::default(), B::default())
     }
 }
 
-impl<K, V> Hashmap<K, V>
+impl<K, V, S, B> PartialEq for Hashmap<K, V, S, B>
 where
     K: Eq + Hash,
+    V: PartialEq,
+    S: BuildHasher,
+    B: TableBackend<K, V>,
 {
-    fn bucket<Q>(&self, key: &Q) -> usize 
-    where 
+    /// Same entries, regardless of hasher or insertion order.
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.into_iter().all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl<K, V, S, B> Eq for Hashmap<K, V, S, B>
+where
+    K: Eq + Hash,
+    V: Eq,
+    S: BuildHasher,
+    B: TableBackend<K, V>,
+{
+}
+
+impl<K, V, S, B> Hash for Hashmap<K, V, S, B>
+where
+    K: Hash,
+    V: Hash,
+    B: TableBackend<K, V>,
+{
+    /// Order-independent: each entry is hashed on its own (with a fixed
+    /// hasher, so maps with different `S` still agree) and the results
+    /// are summed.
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        let fixed = DefaultHashBuilder::default();
+        let sum = self
+            .into_iter()
+            .fold(0u64, |sum, entry| sum.wrapping_add(fixed.hash_one(entry)));
+        state.write_usize(self.len());
+        state.write_u64(sum);
+    }
+}
+
+fn make_hash<Q, S>(hash_builder: &S, key: &Q) -> u64
+where
+    Q: Hash + ?Sized,
+    S: BuildHasher,
+{
+    hash_builder.hash_one(key)
+}
+
+/// hashes an entry's key, for `RawTable::validate`
+fn hash_entry<K: Hash, V, S: BuildHasher>(hash_builder: &S) -> impl Fn(&(K, V)) -> u64 + '_ {
+    move |(key, _)| make_hash(hash_builder, key)
+}
+
+/// Why `Hashmap::rename_key` left the map as it was; the new key is
+/// handed back either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenameError<K> {
+    /// there was no entry to rename
+    Missing(K),
+    /// another entry already has the new key
+    Occupied(K),
+}
+
+impl<K> fmt::Display for RenameError<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenameError::Missing(_) => f.write_str("no entry under the old key"),
+            RenameError::Occupied(_) => f.write_str("the new key is already in use"),
+        }
+    }
+}
+
+impl<K: fmt::Debug> Error for RenameError<K> {}
+
+/// reports a key that isn't in `table` yet about to go in at `hash`
+fn record_new_key<K, V>(metrics: &dyn MetricsSink, table: &impl TableBackend<K, V>, hash: u64) {
+    metrics.record_insert();
+    // the lookup for a missing key passes whatever shares its bucket
+    if table.find_probed(hash, |_| false).1 > 0 {
+        metrics.record_collision();
+    }
+}
+
+impl<K, V, S, B> Hashmap<K, V, S, B>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+    B: TableBackend<K, V>,
+{
+    /// Makes room for `additional` more entries, so inserting them won't
+    /// grow the table.
+    pub fn reserve(&mut self, additional: usize) {
+        let old_buckets = self.table.bucket_count();
+        self.table.reserve(additional);
+        if let Some(metrics) = &self.metrics {
+            if self.table.bucket_count() != old_buckets {
+                metrics.record_resize(old_buckets, self.table.bucket_count());
+            }
+        }
+    }
+
+    /// `find` on the table, reporting the probe length
+    fn lookup<Q>(&self, hash: u64, key: &Q) -> Option<B::Slot>
+    where
         K: Borrow<Q>,
-        Q: Hash + Eq + ?Sized,
+        Q: Eq + ?Sized,
     {
-        let mut hasher = DefaultHasher::new();
-        key.hash(&mut hasher);
-        (hasher.finish() & (self.buckets.len() - 1) as u64) as usize
+        let Some(metrics) = &self.metrics else {
+            return self.table.find(hash, |(ekey, _)| ekey.borrow() == key);
+        };
+        let (slot, probes) = self.table.find_probed(hash, |(ekey, _)| ekey.borrow() == key);
+        metrics.record_probe(probes);
+        slot
+    }
+
+    /// `key`'s slot, inserting `(key, value)` if the key is missing; for
+    /// an existing key the pair is handed back untouched
+    fn insert_slot(&mut self, key: K, value: V) -> (B::Slot, Option<(K, V)>) {
+        let hash = make_hash(&self.hash_builder, &key);
+        self.insert_slot_hashed(hash, key, value)
+    }
+
+    fn insert_slot_hashed(&mut self, hash: u64, key: K, value: V) -> (B::Slot, Option<(K, V)>) {
+        self.reserve(1);
+
+        if let Some(slot) = self.lookup(hash, &key) {
+            return (slot, Some((key, value)));
+        }
+        if let Some(metrics) = &self.metrics {
+            record_new_key(metrics.as_ref(), &self.table, hash);
+        }
+        (self.table.insert_no_grow(hash, (key, value)), None)
     }
 
     pub fn insert(&mut self, key: K, value: V) -> Option<V> {
-        if self.buckets.is_empty() || self.items > 3 * self.buckets.len() / 4 {
-            self.resize();
+        let (slot, pending) = self.insert_slot(key, value);
+        let (_, value) = pending?;
+        Some(mem::replace(&mut self.table.slot_mut(slot).1, value))
+    }
+
+    /// `insert` with `key`'s hash already computed, for structures that
+    /// hashed it themselves. `hash` must be `self.hasher().hash_one(&key)`:
+    /// anything else files the entry where lookups by key won't find it.
+    pub fn insert_hashed(&mut self, hash: u64, key: K, value: V) -> Option<V> {
+        let (slot, pending) = self.insert_slot_hashed(hash, key, value);
+        let (_, value) = pending?;
+        Some(mem::replace(&mut self.table.slot_mut(slot).1, value))
+    }
+
+    /// Inserts without looking for an equal key first, for input known to
+    /// be free of duplicates (and of keys already in the map). Inserting a
+    /// duplicate is not unsafe but leaves two entries for the key; which
+    /// one lookups find, and whether `len` counts both, is unspecified.
+    pub fn insert_unique_unchecked(&mut self, key: K, value: V) -> (&K, &mut V) {
+        self.reserve(1);
+        let hash = make_hash(&self.hash_builder, &key);
+        if let Some(metrics) = &self.metrics {
+            record_new_key(metrics.as_ref(), &self.table, hash);
         }
+        let slot = self.table.insert_no_grow(hash, (key, value));
+        let (key, value) = self.table.slot_mut(slot);
+        (key, value)
+    }
 
-        let bucket = self.bucket(&key);
-        let bucket = &mut self.buckets[bucket];
+    /// Like `insert`, but hands back the displaced key too: the stored key
+    /// is replaced by `key`, which matters when equal keys aren't
+    /// interchangeable.
+    pub fn replace(&mut self, key: K, value: V) -> Option<(K, V)> {
+        let (slot, pending) = self.insert_slot(key, value);
+        Some(mem::replace(self.table.slot_mut(slot), pending?))
+    }
 
-        // ref, we don't wanna take ownership (otherwise we'll break the data structure)
-        for &mut (ref ekey, ref mut eval) in bucket.iter_mut() {
-            if ekey == &key {
-                return Some(mem::replace(eval, value));
-            }
+    /// Like `insert`, returning the stored value to finish initializing it
+    /// in place; a replaced value is dropped.
+    pub fn insert_mut(&mut self, key: K, value: V) -> &mut V {
+        let (slot, pending) = self.insert_slot(key, value);
+        let stored = &mut self.table.slot_mut(slot).1;
+        if let Some((_, value)) = pending {
+            *stored = value;
         }
-        self.items += 1;
-        bucket.push((key, value));
-        None
+        stored
     }
 
-    pub fn resize(&mut self) {
-        let target_size = match self.buckets.len() {
-            0 => INITIAL_NBUCKET,
-            n => 2 * n,
-        };
+    /// Inserts `value`, or if `key` is already there combines the two with
+    /// `merge(&mut old, value)`, all in one lookup. Returns the stored
+    /// value.
+    pub fn insert_merge(&mut self, key: K, value: V, merge: impl FnOnce(&mut V, V)) -> &mut V {
+        let (slot, pending) = self.insert_slot(key, value);
+        let stored = &mut self.table.slot_mut(slot).1;
+        if let Some((_, value)) = pending {
+            merge(stored, value);
+        }
+        stored
+    }
 
-        let mut new_bucket = Vec::with_capacity(target_size);
-        new_bucket.extend((0..target_size).map(|_| Vec::new()));
+    /// `insert_merge` adding the values, e.g. for counters and sums.
+    pub fn insert_add(&mut self, key: K, value: V) -> &mut V
+    where
+        V: AddAssign,
+    {
+        self.insert_merge(key, value, |old, value| *old += value)
+    }
 
-        for (key, value) in self.buckets.iter_mut().flat_map(|bucket| bucket.drain(..)) {
-            let mut hasher = DefaultHasher::new();
-            key.hash(&mut hasher);
-            let bucket: usize = (hasher.finish() & (new_bucket.len() - 1) as u64) as usize;
-            new_bucket[bucket].push((key, value));
+    pub fn resize(&mut self) {
+        let old_buckets = self.table.bucket_count();
+        self.table.resize();
+        if let Some(metrics) = &self.metrics {
+            if self.table.bucket_count() != old_buckets {
+                metrics.record_resize(old_buckets, self.table.bucket_count());
+            }
         }
-        self.buckets = new_bucket;
     }
 
     pub fn get<Q>(&self, key: &Q) -> Option<&V>
@@ -175,10 +796,57 @@ where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        self.buckets[self.bucket(key)]
-            .iter()
-            .find(|(ref ekey, _)| ekey.borrow() == key)
-            .map(|(_, ref eval)| eval)
+        let slot = self.lookup(make_hash(&self.hash_builder, key), key)?;
+        Some(&self.table.slot(slot).1)
+    }
+
+    /// `get` with `key`'s hash already computed; see `insert_hashed`.
+    pub fn get_hashed<Q>(&self, hash: u64, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        let slot = self.lookup(hash, key)?;
+        Some(&self.table.slot(slot).1)
+    }
+
+    /// Looks up every key in `keys`, in order.
+    ///
+    /// All keys are hashed before any bucket is touched, which keeps the
+    /// hashing loop tight and lets the bucket loads of a large batch
+    /// overlap.
+    pub fn get_many<'a, Q>(&self, keys: impl IntoIterator<Item = &'a Q>) -> Vec<Option<&V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized + 'a,
+    {
+        let keys: Vec<(u64, &Q)> = keys
+            .into_iter()
+            .map(|key| (make_hash(&self.hash_builder, key), key))
+            .collect();
+        keys.into_iter()
+            .map(|(hash, key)| self.lookup(hash, key).map(|slot| &self.table.slot(slot).1))
+            .collect()
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let slot = self.lookup(make_hash(&self.hash_builder, key), key)?;
+        Some(&mut self.table.slot_mut(slot).1)
+    }
+
+    /// Like `get_mut`, also lending out the stored key.
+    pub fn get_key_value_mut<Q>(&mut self, key: &Q) -> Option<(&K, &mut V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let slot = self.lookup(make_hash(&self.hash_builder, key), key)?;
+        let (key, value) = self.table.slot_mut(slot);
+        Some((key, value))
     }
 
     pub fn contains_key<Q>(&self, key: &Q) -> bool
@@ -186,9 +854,7 @@ where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        self.buckets[self.bucket(key)]
-            .iter()
-            .any(|(ref ekey, _)| ekey.borrow() == key)
+        self.lookup(make_hash(&self.hash_builder, key), key).is_some()
     }
 
     pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
@@ -196,38 +862,161 @@ where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        let bucket = self.bucket(key);
-        let bucket = &mut self.buckets[bucket];
-        // ? works with both result and option (sugar)
-        let ind: usize = bucket.iter().position(|(ref ekey, _)| ekey.borrow() == key)?;
-        self.items -= 1;
-        Some(bucket.swap_remove(ind).1)
+        self.remove_entry(key).map(|(_, eval)| eval)
     }
 
+    /// Like `remove`, also handing back the stored key.
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.remove_hashed(make_hash(&self.hash_builder, key), key)
+    }
 
-    pub fn entry(&mut self, key: K) -> Entry<K, V> {
-        if self.buckets.is_empty() || self.items > 3 * self.buckets.len() / 4 {
-            self.resize();
+    /// `remove_entry` with `key`'s hash already computed; see
+    /// `insert_hashed`.
+    pub fn remove_hashed<Q>(&mut self, hash: u64, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        let slot = self.lookup(hash, key)?;
+        if let Some(metrics) = &self.metrics {
+            metrics.record_remove();
         }
-        
-        let bucket = self.bucket(&key);
-        match self.buckets[bucket].iter().position(|&(ref ekey, _)| ekey == &key) {
-            Some(index) => Entry::Occupied(OccupiedEntry {
-                element: &mut self.buckets[bucket][index]
-            }),
-            None => Entry::Vacant(VacantEntry {
-                bucket: &mut self.buckets[bucket],
-                key
-            })
+        let old_buckets = self.table.bucket_count();
+        let entry = self.table.erase(slot);
+        self.report_resize(old_buckets);
+        Some(entry)
+    }
+
+    /// Removes `key`'s entry only if `pred` holds for it, in the same
+    /// lookup that found it.
+    pub fn remove_if<Q>(&mut self, key: &Q, pred: impl FnOnce(&K, &V) -> bool) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let slot = self.lookup(make_hash(&self.hash_builder, key), key)?;
+        let (key, value) = self.table.slot(slot);
+        if !pred(key, value) {
+            return None;
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.record_remove();
         }
+        let old_buckets = self.table.bucket_count();
+        let (_, value) = self.table.erase(slot);
+        self.report_resize(old_buckets);
+        Some(value)
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.items == 0
+    /// Moves `old`'s value to the key `new` and returns the old key. A
+    /// `new` equal to `old` just replaces the stored key. Fails, changing
+    /// nothing, if `old` is missing or another entry already has `new`.
+    pub fn rename_key<Q>(&mut self, old: &Q, new: K) -> Result<K, RenameError<K>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let Some(slot) = self.lookup(make_hash(&self.hash_builder, old), old) else {
+            return Err(RenameError::Missing(new));
+        };
+        if new.borrow() == old {
+            return Ok(mem::replace(&mut self.table.slot_mut(slot).0, new));
+        }
+        let hash = make_hash(&self.hash_builder, &new);
+        if self.lookup::<K>(hash, &new).is_some() {
+            return Err(RenameError::Occupied(new));
+        }
+        let old_buckets = self.table.bucket_count();
+        let (old, value) = self.table.erase(slot);
+        self.report_resize(old_buckets);
+        self.reserve(1);
+        self.table.insert_no_grow(hash, (new, value));
+        Ok(old)
     }
 
-    pub fn len(&self) -> usize {
-        self.items
+    /// The value for `key`, inserting `f()` first if there is none. The
+    /// key is only cloned (through `ToOwned`) when it gets inserted.
+    pub fn get_or_insert_with<Q>(&mut self, key: &Q, f: impl FnOnce() -> V) -> &mut V
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        let slot = match self.lookup(hash, key) {
+            Some(slot) => slot,
+            None => {
+                self.reserve(1);
+                if let Some(metrics) = &self.metrics {
+                    record_new_key(metrics.as_ref(), &self.table, hash);
+                }
+                self.table.insert_no_grow(hash, (key.to_owned(), f()))
+            }
+        };
+        &mut self.table.slot_mut(slot).1
+    }
+
+    /// Inserts, modifies or removes `key`'s entry in one lookup: `f` gets
+    /// the current value (if any) and returns the new one, `None` meaning
+    /// no entry. Returns the stored value afterwards.
+    ///
+    /// The entry is out of the map while `f` runs, so a panic in `f`
+    /// leaves it removed.
+    pub fn update<Q>(&mut self, key: &Q, f: impl FnOnce(Option<V>) -> Option<V>) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, key);
+        let (key, value) = match self.lookup(hash, key) {
+            Some(slot) => {
+                let old_buckets = self.table.bucket_count();
+                let (key, value) = self.table.erase(slot);
+                self.report_resize(old_buckets);
+                match f(Some(value)) {
+                    Some(value) => (key, value),
+                    None => {
+                        if let Some(metrics) = &self.metrics {
+                            metrics.record_remove();
+                        }
+                        return None;
+                    }
+                }
+            }
+            None => {
+                let value = f(None)?;
+                // growing keeps `hash` valid, it only moves buckets
+                self.reserve(1);
+                if let Some(metrics) = &self.metrics {
+                    record_new_key(metrics.as_ref(), &self.table, hash);
+                }
+                (key.to_owned(), value)
+            }
+        };
+        let slot = self.table.insert_no_grow(hash, (key, value));
+        Some(&mut self.table.slot_mut(slot).1)
+    }
+
+
+    /// Looks `key` up once. Only inserting through the `Vacant` entry
+    /// grows the table, so an occupied or dropped entry never resizes.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, B> {
+        let hash = make_hash(&self.hash_builder, &key);
+        match self.lookup(hash, &key) {
+            Some(slot) => Entry::Occupied(OccupiedEntry {
+                element: self.table.slot_mut(slot)
+            }),
+            None => Entry::Vacant(VacantEntry {
+                table: &mut self.table,
+                metrics: self.metrics.as_deref(),
+                hash,
+                key,
+                marker: PhantomData,
+            })
+        }
     }
 }
 
@@ -256,6 +1045,533 @@ mod tests {
         assert_eq!(map.remove(&"foobar"), None);
     }
 
+    #[test]
+    fn update() {
+        let mut stock: Hashmap<String, u32> = Hashmap::new();
+        let add = |n: u32| move |old: Option<u32>| Some(old.unwrap_or(0) + n);
+        assert_eq!(stock.update("apples", add(3)), Some(&mut 3));
+        assert_eq!(stock.update("apples", add(2)), Some(&mut 5));
+        // taking the last ones removes the entry
+        let take = |n: u32| move |old: Option<u32>| old.and_then(|v| v.checked_sub(n)).filter(|&v| v > 0);
+        assert_eq!(stock.update("apples", take(5)), None);
+        assert!(stock.is_empty());
+        assert_eq!(stock.update("pears", take(1)), None);
+        assert!(stock.is_empty());
+    }
+
+    #[test]
+    fn get_or_insert_with() {
+        let mut cache: Hashmap<String, Vec<u32>> = Hashmap::new();
+        cache.get_or_insert_with("a", Vec::new).push(1);
+        cache.get_or_insert_with("a", || unreachable!()).push(2);
+        assert_eq!(cache.get("a"), Some(&vec![1, 2]));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn insert_mut() {
+        let mut map = Hashmap::new();
+        map.insert_mut("log", Vec::new()).push("started");
+        let log = map.insert_mut("log", vec!["restarted"]);
+        log.push("ready");
+        assert_eq!(map.get("log"), Some(&vec!["restarted", "ready"]));
+    }
+
+    #[test]
+    fn replace() {
+        // equal by name only
+        #[derive(Debug)]
+        struct Tagged(&'static str, u32);
+        impl PartialEq for Tagged {
+            fn eq(&self, other: &Self) -> bool {
+                self.0 == other.0
+            }
+        }
+        impl Eq for Tagged {}
+        impl Hash for Tagged {
+            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                self.0.hash(state)
+            }
+        }
+
+        let mut map = Hashmap::new();
+        assert!(map.replace(Tagged("a", 1), "first").is_none());
+        let (old, value) = map.replace(Tagged("a", 2), "second").unwrap();
+        assert_eq!((old.1, value), (1, "first"));
+        let (key, value) = map.remove_entry(&Tagged("a", 0)).unwrap();
+        assert_eq!((key.1, value), (2, "second"));
+    }
+
+    #[test]
+    fn pop() {
+        let mut map = Hashmap::new();
+        for i in 0..20 {
+            map.insert(i, i * 3);
+        }
+        let mut popped = Vec::new();
+        while let Some((k, v)) = map.pop() {
+            assert_eq!(v, k * 3);
+            popped.push(k);
+        }
+        popped.sort();
+        assert_eq!(popped, (0..20).collect::<Vec<_>>());
+        assert!(map.is_empty());
+        assert_eq!(map.pop(), None);
+    }
+
+    #[test]
+    fn entry_grows_only_on_insert() {
+        let mut map: Hashmap<u32, u32> = Hashmap::new();
+        let mut n = 0;
+        // fill up to the last insert before a resize
+        loop {
+            let buckets = map.bucket_count();
+            map.insert(n, n);
+            if map.bucket_count() != buckets && buckets != 0 {
+                map.remove(&n);
+                break;
+            }
+            n += 1;
+        }
+        map.shrink_to_fit();
+        let buckets = map.bucket_count();
+        assert_eq!(map.len() as u32, n);
+
+        *map.entry(0).or_insert(0) += 1;
+        assert!(matches!(map.entry(n), Entry::Vacant(_)));
+        assert_eq!(map.bucket_count(), buckets);
+
+        map.entry(n).or_insert(n);
+        assert!(map.bucket_count() > buckets);
+        assert_eq!(map.get(&0), Some(&1));
+        assert_eq!(map.get(&n), Some(&n));
+    }
+
+    #[test]
+    fn insert_unique_unchecked() {
+        let mut map = Hashmap::new();
+        for i in 0..1_000 {
+            let (key, value) = map.insert_unique_unchecked(i, i);
+            assert_eq!(*key, i);
+            *value += 1;
+        }
+        assert_eq!(map.len(), 1_000);
+        assert_eq!(map.get(&999), Some(&1_000));
+        assert!(map.debug_validate().is_ok());
+    }
+
+    // these compile only if the types are covariant in `K` and `V`, like
+    // std's
+    #[allow(dead_code)]
+    mod variance {
+        use super::*;
+
+        type Short<'a> = (&'a str, &'a str);
+
+        fn map<'a>(map: Hashmap<&'static str, ()>) -> Hashmap<&'a str, ()> {
+            map
+        }
+
+        fn iter<'i, 'a>(iter: Iter<'i, &'static str, &'static str>) -> Iter<'i, &'a str, &'a str> {
+            iter
+        }
+
+        fn into_iter<'a>(iter: IntoIter<&'static str, ()>) -> IntoIter<&'a str, ()> {
+            iter
+        }
+
+        type Backends<'a> = (
+            RobinHoodMap<&'a str, ()>,
+            SwissMap<&'a str, ()>,
+            CuckooMap<&'a str, ()>,
+            HopscotchMap<&'a str, ()>,
+        );
+
+        fn backends<'a>(maps: Backends<'static>) -> Backends<'a> {
+            maps
+        }
+
+        fn wrappers<'a>(
+            maps: (SmallMap<&'static str, ()>, HardenedMap<&'static str, ()>),
+        ) -> (SmallMap<&'a str, ()>, HardenedMap<&'a str, ()>) {
+            maps
+        }
+
+        fn values<'a>(map: Hashmap<u32, Short<'static>>) -> Hashmap<u32, Short<'a>> {
+            map
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "map modified while being iterated")]
+    fn stale_iterator() {
+        let mut map = Hashmap::new();
+        map.insert(1, 1);
+        let mut iter = (&map).into_iter();
+        // what a modification behind the iterator's back looks like to it
+        iter.generation -= 1;
+        iter.next();
+    }
+
+    #[test]
+    fn find() {
+        let mut map = Hashmap::new();
+        for i in 0..10 {
+            map.insert(i, i * i);
+        }
+        assert_eq!(map.find(|_, v| *v == 49), Some((&7, &49)));
+        assert_eq!(map.find(|_, v| *v == 50), None);
+        assert_eq!(map.find_map(|k, v| (*v > 10).then_some(k + 100)), Some(104));
+        let mut swiss = SwissMap::<_, _>::default();
+        swiss.insert("x", 1);
+        assert_eq!(swiss.find(|k, _| k.starts_with('x')), Some((&"x", &1)));
+    }
+
+    #[test]
+    fn remove_many() {
+        let mut map = Hashmap::new();
+        map.set_auto_shrink(true);
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        let buckets = map.bucket_count();
+        let keys: Vec<_> = (0..990).chain([5000]).collect();
+        let removed = map.remove_many(&keys);
+        assert_eq!(removed.len(), 991);
+        assert_eq!(removed[3], Some(3));
+        assert_eq!(removed[990], None);
+        assert_eq!(map.len(), 10);
+        assert!(map.bucket_count() < buckets);
+        assert!(map.debug_validate().is_ok());
+    }
+
+    #[test]
+    fn get_key_value_mut() {
+        let mut map = Hashmap::new();
+        map.insert(String::from("a"), 1);
+        let (key, value) = map.get_key_value_mut("a").unwrap();
+        *value += key.len();
+        assert_eq!(map.get("a"), Some(&2));
+        assert!(map.get_key_value_mut("b").is_none());
+    }
+
+    #[test]
+    fn remove_if() {
+        let mut map = Hashmap::new();
+        map.insert("a", 1);
+        map.insert("b", 2);
+        assert_eq!(map.remove_if("a", |_, v| *v > 1), None);
+        assert_eq!(map.remove_if("b", |_, v| *v > 1), Some(2));
+        assert_eq!(map.remove_if("c", |_, _| true), None);
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn rename_key() {
+        let mut files = Hashmap::new();
+        files.insert(String::from("a.txt"), 1);
+        files.insert(String::from("b.txt"), 2);
+        assert_eq!(files.rename_key("a.txt", String::from("c.txt")).as_deref(), Ok("a.txt"));
+        assert_eq!(files.get("c.txt"), Some(&1));
+        assert!(!files.contains_key("a.txt"));
+        assert_eq!(
+            files.rename_key("c.txt", String::from("b.txt")),
+            Err(RenameError::Occupied(String::from("b.txt")))
+        );
+        assert_eq!(
+            files.rename_key("a.txt", String::from("d.txt")),
+            Err(RenameError::Missing(String::from("d.txt")))
+        );
+        assert!(files.rename_key("b.txt", String::from("b.txt")).is_ok());
+        assert_eq!(files.len(), 2);
+        assert_eq!(files.get("b.txt"), Some(&2));
+    }
+
+    #[test]
+    fn insert_merge() {
+        let mut map = Hashmap::new();
+        for (key, value) in [("a", 3), ("b", 1), ("a", 5), ("b", 2)] {
+            map.insert_merge(key, value, |old, value| *old = (*old).max(value));
+        }
+        assert_eq!(map.get("a"), Some(&5));
+        assert_eq!(map.get("b"), Some(&2));
+        assert_eq!(*map.insert_add("a", 10), 15);
+        assert_eq!(*map.insert_add("c", 1), 1);
+        assert_eq!(map.len(), 3);
+    }
+
+    #[test]
+    fn by_value() {
+        let mut counts = Hashmap::new();
+        for (i, word) in ["a", "b", "c", "d", "e"].into_iter().enumerate() {
+            counts.insert(word, (i * 7) % 5);
+        }
+        // a: 0, b: 2, c: 4, d: 1, e: 3
+        assert_eq!(counts.top_n_by_value(2, Ord::cmp), [(&"c", &4), (&"e", &3)]);
+        assert_eq!(counts.top_n_by_value(9, Ord::cmp).len(), 5);
+        assert!(counts.top_n_by_value(0, Ord::cmp).is_empty());
+        let keys: Vec<_> = counts.iter_sorted_by_value(Ord::cmp).map(|(key, _)| *key).collect();
+        assert_eq!(keys, ["a", "d", "b", "e", "c"]);
+    }
+
+    #[test]
+    fn iteration_order() {
+        fn run<S: BuildHasher>(mut map: Hashmap<&'static str, u32, S>) -> Vec<&'static str> {
+            for (i, key) in ["a", "b", "c", "d", "e"].into_iter().enumerate() {
+                map.insert(key, i as u32);
+            }
+            map.remove("b");
+            map.insert("f", 5);
+            map.reserve(1_000);
+            map.shrink_to_fit();
+            map.into_iter().map(|(key, _)| key).collect()
+        }
+        let order = run(Hashmap::new());
+        assert_eq!(order, ["a", "e", "c", "d", "f"]);
+        assert_eq!(run(Hashmap::with_hasher(std::hash::RandomState::new())), order);
+    }
+
+    #[test]
+    fn dropck() {
+        // the maps are declared first, so dropped after `key` and `value`:
+        // that compiles only while dropping them can't touch the borrowed
+        // entries, as with std's map
+        let mut map = Hashmap::new();
+        let mut swiss = SwissMap::<_, _>::default();
+        let mut hopscotch = HopscotchMap::<_, _>::default();
+        let mut small = SmallMap::new();
+        let (key, value) = (String::from("k"), String::from("v"));
+        map.insert(&key, &value);
+        swiss.insert(&key, &value);
+        hopscotch.insert(&key, &value);
+        small.insert(&key, &value);
+        assert_eq!(map.get(&&key), Some(&&value));
+    }
+
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Hashmap<String, Vec<u8>>>();
+        assert_send_sync::<Iter<'_, String, Vec<u8>>>();
+        assert_send_sync::<IntoIter<String, Vec<u8>>>();
+        assert_send_sync::<Entry<'_, String, Vec<u8>>>();
+        assert_send_sync::<RobinHoodMap<String, Vec<u8>>>();
+        assert_send_sync::<SwissMap<String, Vec<u8>>>();
+        assert_send_sync::<CuckooMap<String, Vec<u8>>>();
+        assert_send_sync::<HopscotchMap<String, Vec<u8>>>();
+        assert_send_sync::<SmallMap<String, Vec<u8>>>();
+        assert_send_sync::<HardenedMap<String, Vec<u8>>>();
+    }
+
+    #[test]
+    fn or_try_insert_with() {
+        let mut map = Hashmap::new();
+        let failed: Result<&mut u32, &str> = map.entry("a").or_try_insert_with(|| Err("unavailable"));
+        assert_eq!(failed, Err("unavailable"));
+        assert!(map.is_empty());
+        assert_eq!(map.entry("a").or_try_insert_with(|| Ok::<_, ()>(1)), Ok(&mut 1));
+        assert_eq!(map.entry("a").or_try_insert_with(|| Err(())), Ok(&mut 1));
+    }
+
+    #[test]
+    fn retain_budgeted() {
+        let mut map: Hashmap<u32, u32> = (0..1_000).map(|i| (i, i)).collect();
+        assert!(!map.retain_budgeted(|&k, _| k % 3 == 0, 100));
+        // inserted mid-pass, so checked in this pass
+        map.insert(1_001, 0);
+        let mut calls = 1;
+        while !map.retain_budgeted(|&k, _| k % 3 == 0, 100) {
+            calls += 1;
+        }
+        assert_eq!(calls, 10);
+        assert_eq!(map.len(), 334);
+        assert!((&map).into_iter().all(|(k, _)| k % 3 == 0));
+        assert!(map.retain_budgeted(|_, _| true, usize::MAX));
+    }
+
+    #[test]
+    fn try_entry() {
+        let mut map = Hashmap::new();
+        *map.try_entry("a").unwrap().or_insert(0) += 1;
+        *map.try_entry("a").unwrap().or_insert(0) += 1;
+        assert_eq!(map.get("a"), Some(&2));
+        assert!(map.try_reserve(usize::MAX).is_err());
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn reserve_exact() {
+        let mut map: Hashmap<u32, u32> = Hashmap::new();
+        map.reserve_exact(1000);
+        // 3/4 of 1024 is too few, 1000 fit in 2048
+        assert_eq!(map.bucket_count(), 2048);
+        assert_eq!(map.stats().len, 0);
+        assert_eq!(map.table.allocation_size(), 1000 * (8 + 8 + 4) + 2048 * 4);
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.bucket_count(), 2048);
+    }
+
+    #[test]
+    fn rehash_to() {
+        let mut map = Hashmap::new();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        map.rehash_to(1024);
+        assert_eq!(map.bucket_count(), 1024);
+        map.rehash_to(8);
+        assert_eq!(map.bucket_count(), 8);
+        assert!(map.debug_validate().is_ok());
+        assert_eq!(map.get(&42), Some(&42));
+        // back to the normal policy
+        map.insert(100, 100);
+        assert_eq!(map.bucket_count(), 256);
+    }
+
+    #[test]
+    fn hashed() {
+        let mut map = Hashmap::new();
+        let hash = map.hasher().hash_one("a");
+        assert_eq!(map.insert_hashed(hash, "a", 1), None);
+        assert_eq!(map.insert_hashed(hash, "a", 2), Some(1));
+        assert_eq!(map.get("a"), Some(&2));
+        assert_eq!(map.get_hashed(hash, "a"), Some(&2));
+        // a wrong hash just misses
+        assert_eq!(map.get_hashed(hash ^ 1, "a"), None);
+        assert_eq!(map.remove_hashed(hash, "a"), Some(("a", 2)));
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn empty_lookups() {
+        let mut map: Hashmap<String, u32> = Hashmap::new();
+        assert_eq!(map.get("a"), None);
+        assert!(!map.contains_key("a"));
+        assert_eq!(map.remove("a"), None);
+        assert_eq!(map.get_mut("a"), None);
+        assert_eq!(map.get_many(["a", "b"]), [None, None]);
+
+        // emptied back down to no buckets at all
+        map.insert("a".to_string(), 1);
+        map.remove("a");
+        map.shrink_to_fit();
+        assert_eq!(map.get("a"), None);
+        assert_eq!(map.remove("a"), None);
+        assert_eq!(map.pop(), None);
+    }
+
+    #[test]
+    fn panicking_key() {
+        use std::{
+            cell::Cell,
+            panic::{catch_unwind, AssertUnwindSafe},
+        };
+
+        thread_local! {
+            static PANIC: Cell<bool> = const { Cell::new(false) };
+            static LIVE: Cell<isize> = const { Cell::new(0) };
+        }
+        struct Touchy(u32);
+        impl Touchy {
+            fn new(id: u32) -> Self {
+                LIVE.set(LIVE.get() + 1);
+                Touchy(id)
+            }
+        }
+        impl Drop for Touchy {
+            fn drop(&mut self) {
+                LIVE.set(LIVE.get() - 1);
+            }
+        }
+        impl Hash for Touchy {
+            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                assert!(!PANIC.get(), "hash");
+                self.0.hash(state);
+            }
+        }
+        impl PartialEq for Touchy {
+            fn eq(&self, other: &Self) -> bool {
+                assert!(!PANIC.get(), "eq");
+                self.0 == other.0
+            }
+        }
+        impl Eq for Touchy {}
+
+        let mut map = Hashmap::new();
+        for i in 0..100 {
+            map.insert(Touchy::new(i), i);
+        }
+        PANIC.set(true);
+        assert!(catch_unwind(AssertUnwindSafe(|| map.insert(Touchy::new(100), 100))).is_err());
+        assert!(catch_unwind(AssertUnwindSafe(|| map.get(&Touchy::new(1)))).is_err());
+        // growing rehashes nothing, so it can't hit the panic
+        map.reserve(10_000);
+        assert!(catch_unwind(AssertUnwindSafe(|| map.retain(|_, &mut v| v != 50 || panic!()))).is_err());
+        PANIC.set(false);
+
+        assert_eq!(map.len(), 100);
+        assert!(map.debug_validate().is_ok());
+        assert_eq!(map.get(&Touchy::new(99)), Some(&99));
+        drop(map);
+        assert_eq!(LIVE.get(), 0);
+    }
+
+    #[test]
+    fn max_load_factor() {
+        let mut map = Hashmap::with_max_load_factor(0.5);
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        let load = map.stats().load_factor;
+        assert!(load > 0.25 && load <= 0.5);
+    }
+
+    #[test]
+    fn shrink() {
+        let mut map = Hashmap::new();
+        map.set_auto_shrink(true);
+        for i in 0..10_000 {
+            map.insert(i, i);
+        }
+        map.retain(|&k, _| k < 10);
+        assert!(map.table.bucket_count() <= 64);
+        assert_eq!(map.get(&9), Some(&9));
+        map.retain(|_, _| false);
+        map.shrink_to_fit();
+        assert_eq!(map.table.bucket_count(), 0);
+        map.insert(1, 1);
+        assert_eq!(map.get(&1), Some(&1));
+    }
+
+    #[test]
+    fn get_many() {
+        let mut map = Hashmap::new();
+        for i in 0..100 {
+            map.insert(i.to_string(), i);
+        }
+        let found = map.get_many(["7", "missing", "42"]);
+        assert_eq!(found, [Some(&7), None, Some(&42)]);
+        assert!(map.get_many::<str>([]).is_empty());
+    }
+
+    #[test]
+    fn retain() {
+        let mut map = Hashmap::new();
+        for i in 0..10 {
+            map.insert(i, i * 10);
+        }
+        map.retain(|k, v| {
+            *v += 1;
+            k % 2 == 0
+        });
+        assert_eq!(map.len(), 5);
+        assert_eq!(map.get(&4), Some(&41));
+        assert_eq!(map.get(&5), None);
+    }
+
     #[test]
     fn iter() {
         let mut map = Hashmap::new();
@@ -271,5 +1587,49 @@ mod tests {
             }
         }
         assert_eq!((&map).into_iter().count(), 3);
+
+        let mut owned: Vec<_> = map.into_iter().collect();
+        owned.sort();
+        assert_eq!(owned, [("bar", 2), ("foo", 1), ("foobar", 3)]);
+    }
+
+    #[test]
+    fn eq_and_hash() {
+        let mut a = Hashmap::new();
+        let mut b = Hashmap::new();
+        for i in 0..50 {
+            a.insert(i, i * 2);
+            b.insert(49 - i, (49 - i) * 2);
+        }
+        // b grew through a different insertion order
+        b.resize();
+        assert!(a == b);
+        let hash = |map: &Hashmap<i32, i32>| DefaultHashBuilder::default().hash_one(map);
+        assert_eq!(hash(&a), hash(&b));
+
+        b.insert(7, 0);
+        assert!(a != b);
+        assert_ne!(hash(&a), hash(&b));
+
+        let mut maps = Hashmap::new();
+        maps.insert(a.clone(), "a");
+        assert_eq!(maps.get(&a), Some(&"a"));
+    }
+
+    #[test]
+    fn hashers() {
+        // the default hasher is deterministic: two maps built the same way
+        // lay their entries out the same way
+        let mut a = Hashmap::new();
+        let mut b: Hashmap<_, _> = Hashmap::default();
+        for i in 0..100 {
+            a.insert(i, i);
+            b.insert(i, i);
+        }
+        assert!((&a).into_iter().eq(&b));
+
+        let mut map = Hashmap::with_hasher(std::hash::RandomState::new());
+        map.insert("foo", 1);
+        assert_eq!(map.get("foo"), Some(&1));
     }
 }
@@ -0,0 +1,55 @@
+//! `hashmap!` / `hashset!` literals
+
+/// Builds a `Hashmap` from `key => value` pairs, presized for all of
+/// them: `hashmap! { "a" => 1, "b" => 2 }`.
+#[macro_export]
+macro_rules! hashmap {
+    (@unit $x:expr) => {
+        ()
+    };
+    (@count $($x:expr),*) => {
+        <[()]>::len(&[$($crate::hashmap!(@unit $x)),*])
+    };
+    () => {
+        $crate::Hashmap::new()
+    };
+    ($($key:expr => $value:expr),+ $(,)?) => {{
+        let mut map = $crate::Hashmap::new();
+        map.reserve($crate::hashmap!(@count $($key),+));
+        $(map.insert($key, $value);)+
+        map
+    }};
+}
+
+/// Builds a set, spelled `Hashmap<T, ()>` in this crate, from its
+/// elements: `hashset! { 1, 2, 3 }`.
+#[macro_export]
+macro_rules! hashset {
+    () => {
+        $crate::Hashmap::new()
+    };
+    ($($key:expr),+ $(,)?) => {
+        $crate::hashmap!($($key => ()),+)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Hashmap;
+
+    #[test]
+    fn literals() {
+        let map = hashmap! {
+            "a" => 1,
+            "b" => 2,
+        };
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get("a"), Some(&1));
+        let empty: Hashmap<u8, u8> = hashmap! {};
+        assert!(empty.is_empty());
+
+        let set = hashset! { 3, 1, 3 };
+        assert_eq!(set.len(), 2);
+        assert!(set.contains_key(&1));
+    }
+}
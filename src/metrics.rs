@@ -0,0 +1,157 @@
+//! hooks for feeding a map's activity into telemetry
+//!
+//! Install a `MetricsSink` with `Hashmap::with_metrics`; a map without one
+//! pays nothing beyond an `Option` check per operation.
+
+/// Receives a map's events. Every method defaults to doing nothing, so
+/// implement only the counters you export.
+///
+/// Called synchronously from inside map operations: keep it cheap (an
+/// atomic increment, not a network call).
+pub trait MetricsSink: Send + Sync {
+    /// a new key was inserted (replacing an existing key's value isn't one)
+    fn record_insert(&self) {}
+    fn record_remove(&self) {}
+    /// the table went from `old_buckets` to `new_buckets`, growing or
+    /// (with auto-shrink, or by hand) shrinking
+    fn record_resize(&self, old_buckets: usize, new_buckets: usize) {
+        let _ = (old_buckets, new_buckets);
+    }
+    /// a new key landed in a bucket already holding other keys
+    fn record_collision(&self) {}
+    /// a lookup walked past `probes` entries, most of which it didn't
+    /// need to compare keys with (see `TableBackend::find_probed`)
+    fn record_probe(&self, probes: usize) {
+        let _ = probes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Hashmap;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[derive(Default)]
+    struct Counters {
+        inserts: AtomicUsize,
+        removes: AtomicUsize,
+        resizes: AtomicUsize,
+        shrinks: AtomicUsize,
+        // the bucket count of the latest resize
+        buckets: AtomicUsize,
+        collisions: AtomicUsize,
+        probes: AtomicUsize,
+        probe_total: AtomicUsize,
+    }
+
+    impl MetricsSink for Counters {
+        fn record_insert(&self) {
+            self.inserts.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn record_remove(&self) {
+            self.removes.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn record_resize(&self, old_buckets: usize, new_buckets: usize) {
+            self.resizes.fetch_add(1, Ordering::Relaxed);
+            self.buckets.store(new_buckets, Ordering::Relaxed);
+            if new_buckets < old_buckets {
+                self.shrinks.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        fn record_collision(&self) {
+            self.collisions.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn record_probe(&self, probes: usize) {
+            self.probes.fetch_add(1, Ordering::Relaxed);
+            self.probe_total.fetch_add(probes, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn counts() {
+        let counters = Arc::new(Counters::default());
+        let mut map = Hashmap::with_metrics(counters.clone());
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        map.insert(5, 50);
+        *map.entry(200).or_insert(0) += 1;
+        map.remove(&7);
+        map.remove(&7);
+        map.retain(|k, _| k % 2 == 0);
+        assert_eq!(map.split_off_by(|&k, _| k >= 200).len(), 1);
+        assert!(map.get(&4).is_some());
+
+        assert_eq!(counters.inserts.load(Ordering::Relaxed), 101);
+        assert_eq!(counters.removes.load(Ordering::Relaxed), 51);
+        assert!(counters.resizes.load(Ordering::Relaxed) > 0);
+        assert_eq!(counters.shrinks.load(Ordering::Relaxed), 0);
+        // one lookup per insert, entry, remove and get
+        assert_eq!(counters.probes.load(Ordering::Relaxed), 101 + 1 + 2 + 1);
+        // 101 keys in 256 buckets: some share one, whatever the hasher
+        assert!(counters.collisions.load(Ordering::Relaxed) > 0);
+        assert!(counters.probe_total.load(Ordering::Relaxed) > 0);
+    }
+
+    #[test]
+    fn shrinks() {
+        let counters = Arc::new(Counters::default());
+        let mut map = Hashmap::with_metrics(counters.clone());
+        map.set_auto_shrink(true);
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        for i in 0..900 {
+            map.remove(&i);
+        }
+        let shrinks = counters.shrinks.load(Ordering::Relaxed);
+        assert!(shrinks > 0);
+        assert_eq!(counters.buckets.load(Ordering::Relaxed), map.bucket_count());
+        map.remove_many(&(900..990).collect::<Vec<_>>());
+        assert!(counters.shrinks.load(Ordering::Relaxed) > shrinks);
+        assert_eq!(counters.buckets.load(Ordering::Relaxed), map.bucket_count());
+    }
+
+    /// distinct hashes that all land in bucket 0 of any table with fewer
+    /// than 2^32 buckets
+    #[derive(Default)]
+    struct HighBits(u64);
+
+    impl std::hash::Hasher for HighBits {
+        fn write(&mut self, bytes: &[u8]) {
+            for &byte in bytes {
+                self.0 = self.0 << 8 | byte as u64;
+            }
+        }
+
+        fn finish(&self) -> u64 {
+            self.0 << 32
+        }
+    }
+
+    #[test]
+    fn collisions_and_probe_lengths() {
+        let counters = Arc::new(Counters::default());
+        let mut map = Hashmap::with_hasher_and_metrics(
+            std::hash::BuildHasherDefault::<HighBits>::default(),
+            counters.clone(),
+        );
+        for i in 0..10u32 {
+            map.insert(i, ());
+        }
+        // each insert after the first walks the whole chain before it
+        assert_eq!(counters.collisions.load(Ordering::Relaxed), 9);
+        assert_eq!(counters.probe_total.load(Ordering::Relaxed), 45);
+        assert_eq!(map.get(&100), None);
+        assert_eq!(counters.probe_total.load(Ordering::Relaxed), 45 + 10);
+        assert_eq!(map.stats().max_chain_len, 10);
+    }
+}
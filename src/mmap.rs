@@ -0,0 +1,283 @@
+//! read-only maps persisted to a file and opened through `mmap`
+//!
+//! `MmapMap::write` lays a frozen map out on disk; `MmapMap::open` maps the
+//! file and answers lookups straight out of the mapping, so several
+//! processes opening the same file share one copy in the page cache.
+//!
+//! Layout (all integers little-endian):
+//!
+//! ```text
+//! header   magic "RHMMAP\0\0", version: u32, reserved: u32,
+//!          bucket_count: u64, items: u64
+//! buckets  bucket_count + 1 u64 offsets into the entry area, entries of
+//!          bucket i live in offsets[i]..offsets[i + 1]
+//! entries  key_len: u32, value_len: u32, key bytes, value bytes
+//! ```
+//!
+//! Keys are placed with 64-bit FNV-1a so the layout doesn't depend on the
+//! std hasher of whoever wrote the file.
+use std::{
+    ffi::{c_int, c_void},
+    fs::File,
+    io::{self, BufWriter, Write},
+    os::unix::io::AsRawFd,
+    path::Path,
+    ptr, slice,
+};
+
+use crate::Hashmap;
+
+const MAGIC: &[u8; 8] = b"RHMMAP\0\0";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 32;
+
+const PROT_READ: c_int = 1;
+const MAP_PRIVATE: c_int = 2;
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: isize,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+}
+
+pub struct MmapMap {
+    ptr: *const u8,
+    mapped_len: usize,
+    bucket_count: usize,
+    items: usize,
+}
+
+// the mapping is private and read-only, nothing ever writes through `ptr`
+unsafe impl Send for MmapMap {}
+unsafe impl Sync for MmapMap {}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn read_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+impl MmapMap {
+    /// Writes `map` to `path` in the layout described in the module docs.
+    pub fn write<K, V, S, P>(map: &Hashmap<K, V, S>, path: P) -> io::Result<()>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+        P: AsRef<Path>,
+    {
+        let bucket_count = map.len().next_power_of_two();
+        let mut buckets: Vec<Vec<(&[u8], &[u8])>> = (0..bucket_count).map(|_| Vec::new()).collect();
+        for (key, value) in map {
+            let (key, value) = (key.as_ref(), value.as_ref());
+            if key.len() > u32::MAX as usize || value.len() > u32::MAX as usize {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "entry too large"));
+            }
+            buckets[(fnv1a(key) & (bucket_count - 1) as u64) as usize].push((key, value));
+        }
+
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(&(bucket_count as u64).to_le_bytes())?;
+        out.write_all(&(map.len() as u64).to_le_bytes())?;
+
+        let mut offset = 0u64;
+        out.write_all(&offset.to_le_bytes())?;
+        for bucket in &buckets {
+            offset += bucket.iter().map(|(k, v)| 8 + k.len() as u64 + v.len() as u64).sum::<u64>();
+            out.write_all(&offset.to_le_bytes())?;
+        }
+        for (key, value) in buckets.iter().flatten() {
+            out.write_all(&(key.len() as u32).to_le_bytes())?;
+            out.write_all(&(value.len() as u32).to_le_bytes())?;
+            out.write_all(key)?;
+            out.write_all(value)?;
+        }
+        out.flush()
+    }
+
+    /// Maps the file at `path` read-only.
+    ///
+    /// # Safety
+    ///
+    /// the file must not be modified (truncated, rewritten) while the
+    /// returned map is alive, lookups read it directly.
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> io::Result<MmapMap> {
+        let file = File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len()).map_err(|_| invalid("file too large"))?;
+        if len < HEADER_LEN {
+            return Err(invalid("truncated header"));
+        }
+        let ptr = mmap(ptr::null_mut(), len, PROT_READ, MAP_PRIVATE, file.as_raw_fd(), 0);
+        if ptr as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // from here on `map` unmaps on every error path
+        let mut map = MmapMap {
+            ptr: ptr as *const u8,
+            mapped_len: len,
+            bucket_count: 0,
+            items: 0,
+        };
+        map.validate_header()?;
+        Ok(map)
+    }
+
+    fn validate_header(&mut self) -> io::Result<()> {
+        let data = self.data();
+        if &data[..8] != MAGIC {
+            return Err(invalid("bad magic"));
+        }
+        if read_u32(data, 8) != Some(VERSION) {
+            return Err(invalid("unsupported version"));
+        }
+        let bucket_count = read_u64(data, 16).and_then(|n| usize::try_from(n).ok());
+        let items = read_u64(data, 24).and_then(|n| usize::try_from(n).ok());
+        let (Some(bucket_count), Some(items)) = (bucket_count, items) else {
+            return Err(invalid("bad header"));
+        };
+        if !bucket_count.is_power_of_two() {
+            return Err(invalid("bucket count is not a power of two"));
+        }
+        let entries_start = (bucket_count + 1)
+            .checked_mul(8)
+            .and_then(|n| n.checked_add(HEADER_LEN))
+            .filter(|&n| n <= data.len())
+            .ok_or_else(|| invalid("truncated bucket table"))?;
+        match read_u64(data, entries_start - 8) {
+            Some(end) if end == (data.len() - entries_start) as u64 => {}
+            _ => return Err(invalid("entry area doesn't match file size")),
+        }
+        self.bucket_count = bucket_count;
+        self.items = items;
+        Ok(())
+    }
+
+    fn data(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.mapped_len) }
+    }
+
+    fn entries(&self) -> &[u8] {
+        &self.data()[HEADER_LEN + (self.bucket_count + 1) * 8..]
+    }
+
+    fn bucket(&self, index: usize) -> Entries<'_> {
+        let table = &self.data()[HEADER_LEN..];
+        let range = read_u64(table, index * 8)
+            .zip(read_u64(table, index * 8 + 8))
+            .and_then(|(start, end)| self.entries().get(start as usize..end as usize));
+        Entries {
+            rest: range.unwrap_or(&[]),
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        let index = (fnv1a(key) & (self.bucket_count - 1) as u64) as usize;
+        self.bucket(index)
+            .find(|&(ekey, _)| ekey == key)
+            .map(|(_, value)| value)
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+
+    pub fn iter(&self) -> Entries<'_> {
+        Entries {
+            rest: self.entries(),
+        }
+    }
+}
+
+impl Drop for MmapMap {
+    fn drop(&mut self) {
+        unsafe {
+            munmap(self.ptr as *mut c_void, self.mapped_len);
+        }
+    }
+}
+
+/// Entries of a `MmapMap`, borrowed from the mapping.
+///
+/// Stops early on a malformed entry rather than reading past it.
+pub struct Entries<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = (&'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key_len = read_u32(self.rest, 0)? as usize;
+        let value_len = read_u32(self.rest, 4)? as usize;
+        let key = self.rest.get(8..8 + key_len)?;
+        let value = self.rest.get(8 + key_len..8 + key_len + value_len)?;
+        self.rest = &self.rest[8 + key_len + value_len..];
+        Some((key, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("rusty_hashmap-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = temp_path("mmap-round-trip");
+        let mut map = Hashmap::new();
+        for i in 0..100u32 {
+            map.insert(format!("key{}", i), i.to_string());
+        }
+        MmapMap::write(&map, &path).unwrap();
+
+        let mapped = unsafe { MmapMap::open(&path) }.unwrap();
+        assert_eq!(mapped.len(), 100);
+        assert_eq!(mapped.get(b"key42"), Some(&b"42"[..]));
+        assert!(!mapped.contains_key(b"key100"));
+        assert_eq!(mapped.iter().count(), 100);
+        drop(mapped);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        let path = temp_path("mmap-garbage");
+        std::fs::write(&path, [0u8; 64]).unwrap();
+        assert!(unsafe { MmapMap::open(&path) }.is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
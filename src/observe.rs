@@ -0,0 +1,214 @@
+//! a `Hashmap` that tells subscribers about every change
+//!
+//! `ObservedMap` wraps a map and calls its observers after each insert,
+//! update and removal, so an index or a UI can follow the map without
+//! diffing it. Observers run synchronously, in subscription order, while
+//! the map is mutably borrowed.
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+    mem,
+    sync::mpsc,
+};
+
+use crate::{DefaultHashBuilder, Entry, Hashmap};
+
+/// A change that was just applied to the map.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Change<'a, K, V> {
+    Inserted { key: &'a K, value: &'a V },
+    Updated { key: &'a K, old: &'a V, new: &'a V },
+    Removed { key: &'a K, value: &'a V },
+}
+
+/// An owned `Change`, as sent by `ObservedMap::subscribe_channel`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OwnedChange<K, V> {
+    Inserted { key: K, value: V },
+    Updated { key: K, old: V, new: V },
+    Removed { key: K, value: V },
+}
+
+impl<K: Clone, V: Clone> Change<'_, K, V> {
+    pub fn cloned(&self) -> OwnedChange<K, V> {
+        match *self {
+            Change::Inserted { key, value } => OwnedChange::Inserted {
+                key: key.clone(),
+                value: value.clone(),
+            },
+            Change::Updated { key, old, new } => OwnedChange::Updated {
+                key: key.clone(),
+                old: old.clone(),
+                new: new.clone(),
+            },
+            Change::Removed { key, value } => OwnedChange::Removed {
+                key: key.clone(),
+                value: value.clone(),
+            },
+        }
+    }
+}
+
+type Observer<K, V> = Box<dyn FnMut(&Change<'_, K, V>) + Send>;
+
+pub struct ObservedMap<K, V, S = DefaultHashBuilder> {
+    map: Hashmap<K, V, S>,
+    observers: Vec<Observer<K, V>>,
+}
+
+fn notify<K, V>(observers: &mut [Observer<K, V>], change: Change<'_, K, V>) {
+    for observer in observers {
+        observer(&change);
+    }
+}
+
+impl<K, V> ObservedMap<K, V> {
+    pub fn new() -> Self {
+        Self::from_map(Hashmap::new())
+    }
+}
+
+impl<K, V> Default for ObservedMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, S> ObservedMap<K, V, S> {
+    /// Wraps `map`; its current entries are not reported.
+    pub fn from_map(map: Hashmap<K, V, S>) -> Self {
+        ObservedMap {
+            map,
+            observers: Vec::new(),
+        }
+    }
+
+    pub fn subscribe(&mut self, observer: impl FnMut(&Change<'_, K, V>) + Send + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// Sends a copy of every change down a channel. Changes are dropped
+    /// once the receiver is gone.
+    pub fn subscribe_channel(&mut self) -> mpsc::Receiver<OwnedChange<K, V>>
+    where
+        K: Clone + Send + 'static,
+        V: Clone + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        self.subscribe(move |change| {
+            let _ = tx.send(change.cloned());
+        });
+        rx
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Keeps only the entries `f` returns `true` for, reporting the others
+    /// as removed.
+    pub fn retain(&mut self, mut f: impl FnMut(&K, &V) -> bool) {
+        let observers = &mut self.observers;
+        self.map.retain(|key, value| {
+            let keep = f(key, value);
+            if !keep {
+                notify(observers, Change::Removed { key, value });
+            }
+            keep
+        });
+    }
+
+    /// the underlying map, for reads the wrapper doesn't forward
+    pub fn map(&self) -> &Hashmap<K, V, S> {
+        &self.map
+    }
+
+    pub fn into_inner(self) -> Hashmap<K, V, S> {
+        self.map
+    }
+}
+
+impl<K, V, S> ObservedMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.map.entry(key) {
+            Entry::Occupied(e) => {
+                let old = mem::replace(&mut e.element.1, value);
+                let (key, new) = (&e.element.0, &e.element.1);
+                notify(&mut self.observers, Change::Updated { key, old: &old, new });
+                Some(old)
+            }
+            Entry::Vacant(e) => {
+                let (key, value) = &*e.insert_entry(value);
+                notify(&mut self.observers, Change::Inserted { key, value });
+                None
+            }
+        }
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (key, value) = self.map.remove_entry(key)?;
+        notify(&mut self.observers, Change::Removed { key: &key, value: &value });
+        Some(value)
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get(key)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn observers() {
+        let mut map = ObservedMap::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        map.subscribe(move |change| sink.lock().unwrap().push(change.cloned()));
+        let changes = map.subscribe_channel();
+
+        map.insert("a", 1);
+        map.insert("a", 2);
+        map.insert("b", 3);
+        assert_eq!(map.remove("a"), Some(2));
+        assert_eq!(map.remove("a"), None);
+        map.retain(|_, _| false);
+        assert!(map.is_empty());
+
+        let expected = vec![
+            OwnedChange::Inserted { key: "a", value: 1 },
+            OwnedChange::Updated { key: "a", old: 1, new: 2 },
+            OwnedChange::Inserted { key: "b", value: 3 },
+            OwnedChange::Removed { key: "a", value: 2 },
+            OwnedChange::Removed { key: "b", value: 3 },
+        ];
+        assert_eq!(*log.lock().unwrap(), expected);
+        assert_eq!(changes.try_iter().collect::<Vec<_>>(), expected);
+    }
+}
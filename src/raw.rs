@@ -0,0 +1,1211 @@
+//! the bucket table the `Hashmap` is built on
+//!
+//! `RawTable<T>` knows nothing about keys: callers hand it a precomputed
+//! hash and an equality closure, which is enough to build other containers
+//! (multi-index maps, join tables, ...) on the same storage.
+//! Only public with the `raw` feature.
+//!
+//! Elements live in one dense `Vec`, in insertion order except that
+//! erasing one moves the last element into its place, each with its hash
+//! alongside. Each bucket is a chain threaded through them by
+//! index: `heads` holds every bucket's first element, `next` each
+//! element's successor in its chain. Growing only rewrites those indices
+//! from the stored hashes: elements never move and are never rehashed.
+//! In incremental mode growing keeps the old `heads` around and moves a
+//! few old buckets' chains over at every insert and erase, so no single
+//! operation relinks the whole table.
+
+const INITIAL_NBUCKET: usize = 1;
+/// end of a chain
+const NONE: u32 = u32::MAX;
+/// an empty bucket: heads hold their first element's index plus one
+/// (wrapping, so `NONE` becomes 0) and fresh buckets come from a zeroed
+/// allocation, which costs nothing until touched
+const EMPTY: u32 = 0;
+const MAX_ITEMS: usize = NONE as usize;
+/// old buckets moved per insert or erase while growing incrementally;
+/// enough to finish well before the next growth
+const MIGRATE_STEP: usize = 8;
+/// 3/4, in the 256ths of an element per bucket `max_load` counts in
+const DEFAULT_MAX_LOAD: usize = 192;
+/// auto-shrinking never goes below this many buckets
+const MIN_SHRINK_NBUCKET: usize = 64;
+
+/// Position of an element inside a `RawTable`.
+///
+/// A slot stays valid until the table is modified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Slot {
+    index: usize,
+}
+
+/// A broken internal invariant, found by `RawTable::validate`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// the bucket count is neither zero nor a power of two
+    BucketCount(usize),
+    /// the number of elements disagrees with what the chains reach
+    ItemCount { recorded: usize, actual: usize },
+    /// a chain points outside the table or loops
+    BrokenLink { slot: Slot },
+    /// an element's stored hash isn't what hashing it gives
+    StaleHash { slot: Slot },
+    /// an element sits in a bucket its hash doesn't map to
+    Misplaced {
+        slot: Slot,
+        bucket: usize,
+        expected_bucket: usize,
+    },
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::BucketCount(n) => write!(f, "bucket count {} is not a power of two", n),
+            Violation::ItemCount { recorded, actual } => {
+                write!(f, "table holds {} elements but its chains reach {}", recorded, actual)
+            }
+            Violation::BrokenLink { slot } => write!(f, "chain broken at element {}", slot.index),
+            Violation::StaleHash { slot } => write!(f, "element {} has a stale hash", slot.index),
+            Violation::Misplaced {
+                slot,
+                bucket,
+                expected_bucket,
+            } => write!(
+                f,
+                "element {} is in bucket {} but belongs in bucket {}",
+                slot.index, bucket, expected_bucket
+            ),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RawTable<T> {
+    entries: Vec<T>,
+    // both parallel to `entries`
+    hashes: Vec<u64>,
+    next: Vec<u32>,
+    heads: Vec<u32>,
+    // while growing incrementally, the heads from before, emptied from
+    // the front: old buckets below `migrated` have moved to `heads`
+    old_heads: Vec<u32>,
+    migrated: usize,
+    incremental: bool,
+    auto_shrink: bool,
+    max_load: usize,
+    // replaces `max_load` and doubling when set
+    growth: Option<std::sync::Arc<dyn crate::growth::GrowthPolicy>>,
+    // where the next `retain_budgeted` call picks up
+    retain_cursor: usize,
+    // bumped by every structural change, so debug builds can catch an
+    // iterator outliving one (which only unsafe code can arrange)
+    #[cfg(debug_assertions)]
+    generation: u64,
+}
+
+/// Owning iterator, from `RawTable::into_iter`.
+pub struct RawIntoIter<T> {
+    inner: std::vec::IntoIter<T>,
+}
+
+/// Mutable iterator, from `RawTable::iter_mut`.
+pub struct RawIterMut<'a, T> {
+    inner: std::slice::IterMut<'a, T>,
+}
+
+pub struct RawIter<'a, T> {
+    inner: std::slice::Iter<'a, T>,
+    #[cfg(debug_assertions)]
+    table: &'a RawTable<T>,
+    #[cfg(debug_assertions)]
+    generation: u64,
+}
+
+/// One bucket's elements in probe order, from `RawTable::iter_bucket`.
+pub struct BucketIter<'a, T> {
+    table: &'a RawTable<T>,
+    next: u32,
+    // walking a bucket that hasn't moved yet, whose old chain also holds
+    // the elements of its sibling: skip those
+    unmigrated: Option<usize>,
+}
+
+impl<T> RawTable<T> {
+    pub const fn new() -> Self {
+        RawTable {
+            entries: Vec::new(),
+            hashes: Vec::new(),
+            next: Vec::new(),
+            heads: Vec::new(),
+            old_heads: Vec::new(),
+            migrated: 0,
+            incremental: false,
+            auto_shrink: false,
+            max_load: DEFAULT_MAX_LOAD,
+            growth: None,
+            retain_cursor: 0,
+            #[cfg(debug_assertions)]
+            generation: 0,
+        }
+    }
+
+    /// A table that grows once it holds more than `max_load_factor`
+    /// elements per bucket, instead of 3/4: lower means shorter chains
+    /// and more buckets.
+    ///
+    /// # Panics
+    ///
+    /// if `max_load_factor` isn't within `0.25..=4.0`
+    pub fn with_max_load_factor(max_load_factor: f32) -> Self {
+        assert!(
+            (0.25..=4.0).contains(&max_load_factor),
+            "max load factor must be within 0.25..=4.0, got {}",
+            max_load_factor
+        );
+        RawTable {
+            max_load: (max_load_factor * 256.0).round() as usize,
+            ..Self::new()
+        }
+    }
+
+    /// A table that grows when and as far as `policy` says, instead of
+    /// doubling past the load factor. Auto-shrinking still goes by the
+    /// default load factor.
+    pub fn with_growth_policy(policy: std::sync::Arc<dyn crate::growth::GrowthPolicy>) -> Self {
+        RawTable {
+            growth: Some(policy),
+            ..Self::new()
+        }
+    }
+
+    fn overloaded(&self, len: usize, buckets: usize) -> bool {
+        // there have to be buckets to insert into, whatever the policy says
+        if buckets == 0 {
+            return true;
+        }
+        match &self.growth {
+            Some(policy) => policy.is_overloaded(len, buckets),
+            None => len > self.max_items(buckets),
+        }
+    }
+
+    /// the bucket count to grow to from `buckets`, if any
+    fn grown(&self, buckets: usize) -> Option<usize> {
+        if buckets == 0 {
+            return Some(INITIAL_NBUCKET);
+        }
+        let Some(policy) = &self.growth else {
+            return Some(2 * buckets);
+        };
+        match policy.grow(buckets) {
+            Some(next) => {
+                assert!(
+                    next > buckets && next.is_power_of_two(),
+                    "growth policy must grow to a larger power of two, got {} from {}",
+                    next,
+                    buckets
+                );
+                Some(next)
+            }
+            None => None,
+        }
+    }
+
+    /// the bucket count `needed` elements grow `buckets` buckets to
+    fn target_buckets(&self, needed: usize, mut buckets: usize) -> usize {
+        while self.overloaded(needed, buckets) {
+            match self.grown(buckets) {
+                Some(next) => buckets = next,
+                None => break,
+            }
+        }
+        buckets
+    }
+
+    /// how many elements `buckets` buckets hold before growing
+    fn max_items(&self, buckets: usize) -> usize {
+        buckets * self.max_load / 256 + 1
+    }
+
+    fn head_to_index(head: u32) -> u32 {
+        head.wrapping_sub(1)
+    }
+
+    fn index_to_head(index: u32) -> u32 {
+        index.wrapping_add(1)
+    }
+
+    #[inline]
+    fn modified(&mut self) {
+        #[cfg(debug_assertions)]
+        {
+            self.generation = self.generation.wrapping_add(1);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// number of buckets, always zero or a power of two
+    pub fn bucket_count(&self) -> usize {
+        self.heads.len()
+    }
+
+    fn bucket_index(&self, hash: u64) -> usize {
+        (hash & (self.heads.len() - 1) as u64) as usize
+    }
+
+    /// the bucket elements hashing to `hash` go in, counting buckets as
+    /// they are once any growth in progress is done
+    ///
+    /// # Panics
+    ///
+    /// if the table has no buckets yet
+    pub fn bucket_of(&self, hash: u64) -> usize {
+        assert!(!self.heads.is_empty(), "RawTable has no buckets yet");
+        self.bucket_index(hash)
+    }
+
+    /// the old bucket `hash` is still chained in, while growing
+    /// incrementally and that bucket hasn't moved yet
+    fn unmigrated_bucket(&self, hash: u64) -> Option<usize> {
+        if self.old_heads.is_empty() {
+            return None;
+        }
+        let old = (hash & (self.old_heads.len() - 1) as u64) as usize;
+        (old >= self.migrated).then_some(old)
+    }
+
+    /// the head of the chain `hash` is in
+    fn head_mut(&mut self, hash: u64) -> &mut u32 {
+        match self.unmigrated_bucket(hash) {
+            Some(old) => &mut self.old_heads[old],
+            None => {
+                let bucket = self.bucket_index(hash);
+                &mut self.heads[bucket]
+            }
+        }
+    }
+
+    /// Grows by doubling the buckets incrementally instead of all at
+    /// once; turning it off finishes any growth in progress.
+    pub fn set_incremental(&mut self, incremental: bool) {
+        self.incremental = incremental;
+        if !incremental {
+            self.finish_migration();
+        }
+    }
+
+    /// Shrinks once the load factor drops below 1/6 of the maximum (1/8
+    /// by default), to between 1/3 and 2/3 of it: far enough from the
+    /// growth threshold that a bursty map doesn't flip back and forth.
+    pub fn set_auto_shrink(&mut self, auto_shrink: bool) {
+        self.auto_shrink = auto_shrink;
+    }
+
+    /// moves up to `buckets` old buckets' chains over to `heads`
+    fn migrate(&mut self, buckets: usize) {
+        let end = (self.migrated + buckets).min(self.old_heads.len());
+        for old in self.migrated..end {
+            let mut index = Self::head_to_index(std::mem::replace(&mut self.old_heads[old], EMPTY));
+            // `migrated` first, so `link_at_head` picks the new buckets
+            self.migrated = old + 1;
+            while index != NONE {
+                let next = self.next[index as usize];
+                self.link_at_head(index);
+                index = next;
+            }
+        }
+        if self.migrated == self.old_heads.len() {
+            self.old_heads = Vec::new();
+            self.migrated = 0;
+        }
+    }
+
+    fn finish_migration(&mut self) {
+        self.migrate(self.old_heads.len());
+    }
+
+    /// the first element of the chain `hash` is in, or `NONE`
+    fn chain_start(&self, hash: u64) -> u32 {
+        if self.heads.is_empty() {
+            return NONE;
+        }
+        Self::head_to_index(match self.unmigrated_bucket(hash) {
+            Some(old) => self.old_heads[old],
+            None => self.heads[self.bucket_index(hash)],
+        })
+    }
+
+    /// how many elements share the chain `hash` is in, i.e. how long a
+    /// lookup for it can take
+    pub fn chain_len(&self, hash: u64) -> usize {
+        let mut index = self.chain_start(hash);
+        let mut len = 0;
+        while index != NONE {
+            len += 1;
+            index = self.next[index as usize];
+        }
+        len
+    }
+
+    pub fn find(&self, hash: u64, eq: impl FnMut(&T) -> bool) -> Option<Slot> {
+        self.find_probed(hash, eq).0
+    }
+
+    /// `find`, also counting the elements of the chain it walked, hash
+    /// match or not
+    pub fn find_probed(&self, hash: u64, mut eq: impl FnMut(&T) -> bool) -> (Option<Slot>, usize) {
+        let mut index = self.chain_start(hash);
+        let mut probes = 0;
+        while index != NONE {
+            let i = index as usize;
+            probes += 1;
+            // only call `eq` on a full hash match
+            if self.hashes[i] == hash && eq(&self.entries[i]) {
+                return (Some(Slot { index: i }), probes);
+            }
+            index = self.next[i];
+        }
+        (None, probes)
+    }
+
+    pub fn get(&self, hash: u64, eq: impl FnMut(&T) -> bool) -> Option<&T> {
+        let slot = self.find(hash, eq)?;
+        Some(self.slot(slot))
+    }
+
+    pub fn get_mut(&mut self, hash: u64, eq: impl FnMut(&T) -> bool) -> Option<&mut T> {
+        let slot = self.find(hash, eq)?;
+        Some(self.slot_mut(slot))
+    }
+
+    /// # Panics
+    ///
+    /// if `slot` doesn't point at an element (e.g. it is stale)
+    pub fn slot(&self, slot: Slot) -> &T {
+        &self.entries[slot.index]
+    }
+
+    /// # Panics
+    ///
+    /// if `slot` doesn't point at an element (e.g. it is stale)
+    pub fn slot_mut(&mut self, slot: Slot) -> &mut T {
+        &mut self.entries[slot.index]
+    }
+
+    /// Makes room for `additional` more elements, growing if the load
+    /// factor would go above the maximum.
+    pub fn reserve(&mut self, additional: usize) {
+        let buckets = self.target_buckets(self.len() + additional, self.heads.len());
+        if buckets != self.heads.len() {
+            if self.incremental && !self.is_empty() && buckets == 2 * self.heads.len() {
+                self.start_growing();
+            } else {
+                self.relink(buckets);
+            }
+        }
+        self.entries.reserve(additional);
+        self.hashes.reserve(additional);
+        self.next.reserve(additional);
+    }
+
+    /// Like `reserve`, but reports allocation failure instead of aborting.
+    /// On error the table's contents and buckets are unchanged.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), std::collections::TryReserveError> {
+        // the hashes are the widest per-element storage (bar `T`), so this
+        // also rules out `len + additional` overflowing below
+        self.hashes.try_reserve(additional)?;
+        self.next.try_reserve(additional)?;
+        self.entries.try_reserve(additional)?;
+        let buckets = self.target_buckets(self.len() + additional, self.heads.len());
+        if buckets == self.heads.len() {
+            return Ok(());
+        }
+        let mut heads = Vec::new();
+        heads.try_reserve_exact(buckets)?;
+        heads.resize(buckets, EMPTY);
+        if self.incremental && !self.is_empty() && buckets == 2 * self.heads.len() {
+            self.finish_migration();
+            self.old_heads = std::mem::replace(&mut self.heads, heads);
+            self.modified();
+        } else {
+            self.relink_into(heads);
+        }
+        Ok(())
+    }
+
+    /// Like `reserve`, but without the slack: straight to the fewest
+    /// buckets that hold `additional` more elements, and element storage
+    /// for exactly that many more.
+    pub fn reserve_exact(&mut self, additional: usize) {
+        let buckets = self.target_buckets(self.len() + additional, self.heads.len());
+        if buckets != self.heads.len() {
+            self.relink(buckets);
+        }
+        self.entries.reserve_exact(additional);
+        self.hashes.reserve_exact(additional);
+        self.next.reserve_exact(additional);
+    }
+
+    /// Inserts `value` under `hash` without checking for an equal element;
+    /// callers wanting map semantics must `find` first.
+    pub fn insert(&mut self, hash: u64, value: T) -> Slot {
+        self.reserve(1);
+        self.insert_no_grow(hash, value)
+    }
+
+    /// Like `insert`, for callers that already called `reserve`.
+    ///
+    /// # Panics
+    ///
+    /// if the table has no buckets yet, or already holds 2^32 - 1 elements
+    pub fn insert_no_grow(&mut self, hash: u64, value: T) -> Slot {
+        let index = self.entries.len();
+        assert!(index < MAX_ITEMS, "RawTable is full");
+        self.entries.push(value);
+        self.hashes.push(hash);
+        self.next.push(NONE);
+        self.link_at_head(index as u32);
+        self.migrate(MIGRATE_STEP);
+        self.modified();
+        Slot { index }
+    }
+
+    fn link_at_head(&mut self, index: u32) {
+        let head = self.head_mut(self.hashes[index as usize]);
+        let next = std::mem::replace(head, Self::index_to_head(index));
+        self.next[index as usize] = Self::head_to_index(next);
+    }
+
+    /// repoints the link to element `index` (its bucket's head or its
+    /// predecessor's `next`) at `to`
+    fn set_link_to(&mut self, index: u32, to: u32) {
+        let head = self.head_mut(self.hashes[index as usize]);
+        if Self::head_to_index(*head) == index {
+            *head = Self::index_to_head(to);
+            return;
+        }
+        // the chain is short, and `index` is on it
+        let mut prev = Self::head_to_index(*head);
+        while self.next[prev as usize] != index {
+            prev = self.next[prev as usize];
+        }
+        self.next[prev as usize] = to;
+    }
+
+    /// Removes the element at `slot`. The last element moves into its
+    /// place, so only that element's slot (and `slot`) are invalidated.
+    pub fn erase(&mut self, slot: Slot) -> T {
+        let removed = self.erase_no_shrink(slot);
+        self.shrink_if_sparse();
+        removed
+    }
+
+    /// `erase` that leaves auto-shrinking to a later `shrink_if_sparse`,
+    /// for removing many elements in a row.
+    pub fn erase_no_shrink(&mut self, slot: Slot) -> T {
+        let index = slot.index as u32;
+        let last = (self.entries.len() - 1) as u32;
+        self.set_link_to(index, self.next[slot.index]);
+        if index != last {
+            self.set_link_to(last, index);
+        }
+        self.hashes.swap_remove(slot.index);
+        self.next.swap_remove(slot.index);
+        self.migrate(MIGRATE_STEP);
+        self.modified();
+        self.entries.swap_remove(slot.index)
+    }
+
+    /// Shrinks if auto-shrinking is on and the table has got sparse
+    /// enough; see `set_auto_shrink`.
+    pub fn shrink_if_sparse(&mut self) {
+        if self.auto_shrink
+            && self.heads.len() > MIN_SHRINK_NBUCKET
+            && self.len() < self.max_items(self.heads.len()) / 6
+        {
+            let mut buckets = self.heads.len();
+            while buckets > MIN_SHRINK_NBUCKET && self.len() < self.max_items(buckets) / 3 {
+                buckets /= 2;
+            }
+            self.relink(buckets);
+            // room to grow back to the next resize without reallocating
+            let capacity = self.max_items(buckets);
+            self.entries.shrink_to(capacity);
+            self.hashes.shrink_to(capacity);
+            self.next.shrink_to(capacity);
+        }
+    }
+
+    pub fn remove(&mut self, hash: u64, eq: impl FnMut(&T) -> bool) -> Option<T> {
+        let slot = self.find(hash, eq)?;
+        Some(self.erase(slot))
+    }
+
+    /// Removes the most recently placed element, in O(1).
+    pub fn pop(&mut self) -> Option<T> {
+        let last = self.entries.len().checked_sub(1)?;
+        Some(self.erase(Slot { index: last }))
+    }
+
+    /// Keeps only the elements `f` returns `true` for.
+    pub fn retain(&mut self, mut f: impl FnMut(&mut T) -> bool) {
+        let mut index = 0;
+        while index < self.entries.len() {
+            if f(&mut self.entries[index]) {
+                index += 1;
+            } else {
+                // the last element moves here, look at it next
+                self.erase(Slot { index });
+            }
+        }
+        self.modified();
+    }
+
+    /// `retain` spread over several calls: checks at most `budget`
+    /// elements, picking up where the previous call stopped, and returns
+    /// `true` once a pass over the whole table is done (the next call
+    /// starts another). Elements inserted mid-pass are checked in the same
+    /// pass. A `remove` between calls moves the last element into the
+    /// removed one's place, and if that place was already checked, the
+    /// moved element waits for the next pass.
+    pub fn retain_budgeted(&mut self, mut f: impl FnMut(&mut T) -> bool, budget: usize) -> bool {
+        let mut index = self.retain_cursor.min(self.entries.len());
+        for _ in 0..budget {
+            if index == self.entries.len() {
+                break;
+            }
+            if f(&mut self.entries[index]) {
+                index += 1;
+            } else {
+                self.erase(Slot { index });
+            }
+        }
+        self.modified();
+        let done = index == self.entries.len();
+        self.retain_cursor = if done { 0 } else { index };
+        done
+    }
+
+    /// A new, bucketless table with this one's load factor, growth policy,
+    /// and incremental and auto-shrink settings.
+    pub fn empty_like<U>(&self) -> RawTable<U> {
+        RawTable {
+            incremental: self.incremental,
+            auto_shrink: self.auto_shrink,
+            max_load: self.max_load,
+            growth: self.growth.clone(),
+            ..RawTable::new()
+        }
+    }
+
+    /// Moves the elements `f` returns `true` for into a new table with
+    /// the same bucket count. Nothing is rehashed.
+    pub fn split_off_by(&mut self, mut f: impl FnMut(&T) -> bool) -> RawTable<T> {
+        let mut other = self.empty_like();
+        other.heads = vec![EMPTY; self.heads.len()];
+        self.old_heads = Vec::new();
+        self.migrated = 0;
+        self.heads.fill(EMPTY);
+        self.next.clear();
+        self.retain_cursor = 0;
+        let entries = std::mem::take(&mut self.entries);
+        let hashes = std::mem::take(&mut self.hashes);
+        for (value, hash) in entries.into_iter().zip(hashes) {
+            let table = if f(&value) { &mut other } else { &mut *self };
+            table.insert_no_grow(hash, value);
+        }
+        self.modified();
+        other
+    }
+
+    /// Moves every element out, leaving the table empty with its buckets
+    /// and settings.
+    pub fn take_entries(&mut self) -> RawIntoIter<T> {
+        self.hashes.clear();
+        self.next.clear();
+        self.heads.fill(EMPTY);
+        self.old_heads = Vec::new();
+        self.migrated = 0;
+        self.retain_cursor = 0;
+        self.modified();
+        RawIntoIter {
+            inner: std::mem::take(&mut self.entries).into_iter(),
+        }
+    }
+
+    /// Converts every element through `f`, keeping each in its bucket: `f`
+    /// must not change what an element hashes to.
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> RawTable<U> {
+        RawTable {
+            entries: self.entries.into_iter().map(f).collect(),
+            hashes: self.hashes,
+            next: self.next,
+            heads: self.heads,
+            old_heads: self.old_heads,
+            migrated: self.migrated,
+            incremental: self.incremental,
+            auto_shrink: self.auto_shrink,
+            max_load: self.max_load,
+            growth: self.growth,
+            retain_cursor: self.retain_cursor,
+            #[cfg(debug_assertions)]
+            generation: 0,
+        }
+    }
+
+    /// Doubles the bucket count (or grows as the growth policy says) and
+    /// rebuilds every chain from the stored hashes; elements stay where
+    /// they are. Finishes any incremental growth first.
+    pub fn resize(&mut self) {
+        let buckets = self.grown(self.heads.len()).unwrap_or(self.heads.len());
+        self.relink(buckets);
+    }
+
+    /// Shrinks to the fewest buckets `reserve` allows for the elements
+    /// there are, and the element storage to fit them.
+    pub fn shrink_to_fit(&mut self) {
+        let buckets = self.target_buckets(self.len(), 0);
+        self.relink(if self.is_empty() { 0 } else { buckets });
+        self.entries.shrink_to_fit();
+        self.hashes.shrink_to_fit();
+        self.next.shrink_to_fit();
+    }
+
+    /// Rebuilds the chains into exactly `buckets` buckets, whatever the
+    /// load factor; later inserts still grow it past the maximum.
+    ///
+    /// # Panics
+    ///
+    /// if `buckets` isn't a power of two
+    pub fn rehash_to(&mut self, buckets: usize) {
+        assert!(buckets.is_power_of_two(), "bucket count must be a power of two, got {}", buckets);
+        self.relink(buckets);
+    }
+
+    /// rebuilds every chain into `buckets` fresh buckets, dropping any
+    /// incremental growth in progress
+    fn relink(&mut self, buckets: usize) {
+        self.relink_into(vec![EMPTY; buckets]);
+    }
+
+    /// `relink` into an already allocated set of empty buckets
+    fn relink_into(&mut self, heads: Vec<u32>) {
+        self.old_heads = Vec::new();
+        self.migrated = 0;
+        self.heads = heads;
+        for index in 0..self.entries.len() {
+            self.link_at_head(index as u32);
+        }
+        self.modified();
+    }
+
+    /// doubles the bucket count, leaving the chains in the old buckets
+    /// for `migrate` to move
+    fn start_growing(&mut self) {
+        self.finish_migration();
+        let target_size = 2 * self.heads.len();
+        self.old_heads = std::mem::replace(&mut self.heads, vec![EMPTY; target_size]);
+        self.modified();
+    }
+
+    /// Bytes allocated for the table itself, by capacity: elements, their
+    /// hashes and links, and bucket heads. Heap memory owned by the
+    /// elements isn't included.
+    pub fn allocation_size(&self) -> usize {
+        use std::mem::size_of;
+        self.entries.capacity() * size_of::<T>()
+            + self.hashes.capacity() * size_of::<u64>()
+            + self.next.capacity() * size_of::<u32>()
+            + (self.heads.capacity() + self.old_heads.capacity()) * size_of::<u32>()
+    }
+
+    /// Checks the table's internal invariants, rehashing every element
+    /// through `hasher`; an empty list means the table is consistent.
+    pub fn validate(&self, hasher: impl Fn(&T) -> u64) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let count = self.heads.len();
+        if count != 0 && !count.is_power_of_two() {
+            violations.push(Violation::BucketCount(count));
+        }
+        let mut actual = 0;
+        // the buckets, then the old buckets still to migrate
+        let chains = (0..count)
+            .map(|bucket| (bucket, self.heads[bucket], count))
+            .chain((self.migrated..self.old_heads.len()).map(|old| (old, self.old_heads[old], self.old_heads.len())));
+        for (bucket, head, count) in chains {
+            let mut index = Self::head_to_index(head);
+            while index != NONE {
+                let slot = Slot {
+                    index: index as usize,
+                };
+                // a stray index or a cycle: this chain can't be trusted
+                if slot.index >= self.entries.len() || actual > self.entries.len() {
+                    violations.push(Violation::BrokenLink { slot });
+                    break;
+                }
+                let hash = hasher(&self.entries[slot.index]);
+                if hash != self.hashes[slot.index] {
+                    violations.push(Violation::StaleHash { slot });
+                }
+                if count.is_power_of_two() {
+                    let expected_bucket = (hash & (count - 1) as u64) as usize;
+                    if expected_bucket != bucket {
+                        violations.push(Violation::Misplaced {
+                            slot,
+                            bucket,
+                            expected_bucket,
+                        });
+                    }
+                }
+                actual += 1;
+                index = self.next[slot.index];
+            }
+        }
+        if actual != self.entries.len() {
+            violations.push(Violation::ItemCount {
+                recorded: self.entries.len(),
+                actual,
+            });
+        }
+        violations
+    }
+
+    pub fn iter(&self) -> RawIter<'_, T> {
+        RawIter {
+            inner: self.entries.iter(),
+            #[cfg(debug_assertions)]
+            table: self,
+            #[cfg(debug_assertions)]
+            generation: self.generation,
+        }
+    }
+
+    /// Mutable access to every element; changing what an element hashes
+    /// to leaves it in the wrong bucket.
+    pub fn iter_mut(&mut self) -> RawIterMut<'_, T> {
+        RawIterMut {
+            inner: self.entries.iter_mut(),
+        }
+    }
+
+    /// elements of bucket `index`, in probe order; while growing
+    /// incrementally, those still to move to it come from the old chain
+    /// they are in
+    ///
+    /// # Panics
+    ///
+    /// if `index >= self.bucket_count()`
+    pub fn iter_bucket(&self, index: usize) -> BucketIter<'_, T> {
+        let head = self.heads[index];
+        let old = index & self.old_heads.len().wrapping_sub(1);
+        if self.old_heads.is_empty() || old < self.migrated {
+            return BucketIter {
+                table: self,
+                next: Self::head_to_index(head),
+                unmigrated: None,
+            };
+        }
+        // inserts go to the old chain until it moves
+        debug_assert_eq!(head, EMPTY);
+        BucketIter {
+            table: self,
+            next: Self::head_to_index(self.old_heads[old]),
+            unmigrated: Some(index),
+        }
+    }
+}
+
+impl<T> Default for RawTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> crate::TableBackend<K, V> for RawTable<(K, V)> {
+    type Slot = Slot;
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn bucket_count(&self) -> usize {
+        self.bucket_count()
+    }
+
+    fn find(&self, hash: u64, eq: impl FnMut(&(K, V)) -> bool) -> Option<Slot> {
+        self.find(hash, eq)
+    }
+
+    fn find_probed(&self, hash: u64, eq: impl FnMut(&(K, V)) -> bool) -> (Option<Slot>, usize) {
+        self.find_probed(hash, eq)
+    }
+
+    fn slot(&self, slot: Slot) -> &(K, V) {
+        self.slot(slot)
+    }
+
+    fn slot_mut(&mut self, slot: Slot) -> &mut (K, V) {
+        self.slot_mut(slot)
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.reserve(additional)
+    }
+
+    fn insert_no_grow(&mut self, hash: u64, entry: (K, V)) -> Slot {
+        self.insert_no_grow(hash, entry)
+    }
+
+    fn erase(&mut self, slot: Slot) -> (K, V) {
+        self.erase(slot)
+    }
+
+    fn resize(&mut self) {
+        self.resize()
+    }
+
+    fn next_run(&self, pos: &mut usize) -> &[(K, V)] {
+        // the elements are dense: everything left is one run
+        let run = self.entries.get(*pos..).unwrap_or_default();
+        *pos = self.entries.len();
+        run
+    }
+
+    #[cfg(debug_assertions)]
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+impl<T> IntoIterator for RawTable<T> {
+    type Item = T;
+    type IntoIter = RawIntoIter<T>;
+
+    fn into_iter(self) -> RawIntoIter<T> {
+        RawIntoIter {
+            inner: self.entries.into_iter(),
+        }
+    }
+}
+
+impl<'a, T> Iterator for RawIterMut<'a, T> {
+    type Item = &'a mut T;
+    fn next(&mut self) -> Option<&'a mut T> {
+        self.inner.next()
+    }
+}
+
+impl<T> Iterator for RawIntoIter<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<T> ExactSizeIterator for RawIntoIter<T> {}
+
+impl<'a, T> Iterator for RawIter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<Self::Item> {
+        #[cfg(debug_assertions)]
+        assert_eq!(
+            self.generation, self.table.generation,
+            "RawTable modified while being iterated"
+        );
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<T> ExactSizeIterator for RawIter<'_, T> {}
+
+impl<'a, T> Iterator for BucketIter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        while self.next != NONE {
+            let index = self.next as usize;
+            self.next = self.table.next[index];
+            match self.unmigrated {
+                Some(bucket) if self.table.bucket_index(self.table.hashes[index]) != bucket => {}
+                _ => return Some(&self.table.entries[index]),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // identity "hash" so the tests control which bucket things land in
+    fn id(v: &u64) -> u64 {
+        *v
+    }
+
+    #[test]
+    fn insert_find_erase() {
+        let mut table = RawTable::new();
+        assert_eq!(table.find(1, |&v| v == 1), None);
+        for v in 0..10u64 {
+            table.insert(v, v);
+        }
+        assert_eq!(table.len(), 10);
+        assert!(table.bucket_count().is_power_of_two());
+
+        let slot = table.find(7, |&v| v == 7).unwrap();
+        assert_eq!(*table.slot(slot), 7);
+        assert_eq!(table.erase(slot), 7);
+        assert_eq!(table.find(7, |&v| v == 7), None);
+        assert_eq!(table.remove(3, |&v| v == 3), Some(3));
+        assert_eq!(table.len(), 8);
+        assert_eq!(table.iter().count(), 8);
+        assert!(table.validate(id).is_empty());
+        for v in [0, 1, 2, 4, 5, 6, 8, 9] {
+            assert_eq!(table.get(v, |&e| e == v), Some(&v));
+        }
+    }
+
+    #[test]
+    fn validate() {
+        let mut table = RawTable::new();
+        assert!(table.validate(id).is_empty());
+        for v in 0..20u64 {
+            table.insert(v, v);
+        }
+        assert!(table.validate(id).is_empty());
+
+        // an element changed behind the table's back
+        table.entries[1] = 0;
+        let violations = table.validate(id);
+        assert_eq!(violations.len(), 2);
+        assert!(matches!(violations[0], Violation::StaleHash { .. }));
+        assert!(matches!(violations[1], Violation::Misplaced { expected_bucket: 0, .. }));
+        table.entries[1] = 1;
+
+        // an element no chain reaches
+        table.entries.push(20);
+        table.hashes.push(20);
+        table.next.push(NONE);
+        assert_eq!(
+            table.validate(id),
+            [Violation::ItemCount { recorded: 21, actual: 20 }]
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "modified while being iterated")]
+    fn stale_iterator() {
+        let mut table = RawTable::new();
+        table.insert(1, 1);
+        let mut iter = table.iter();
+        // what a modification behind the iterator's back looks like to it
+        iter.generation -= 1;
+        iter.next();
+    }
+
+    #[test]
+    fn incremental() {
+        let mut table = RawTable::new();
+        table.set_incremental(true);
+        for v in 0..800u64 {
+            table.insert(v, v);
+            if v % 97 == 0 {
+                assert!(table.validate(id).is_empty());
+                assert!((0..=v).all(|e| table.find(e, |&x| x == e).is_some()));
+            }
+        }
+        // caught mid-growth, elements in both the old and the new buckets
+        assert!(!table.old_heads.is_empty());
+        // buckets read as they will be once growing is done
+        assert!((0..800u64).all(|v| table.iter_bucket(table.bucket_of(v)).any(|&e| e == v)));
+        let reached: usize = (0..table.bucket_count()).map(|b| table.iter_bucket(b).count()).sum();
+        assert_eq!(reached, 800);
+        for v in (0..800u64).step_by(3) {
+            assert_eq!(table.remove(v, |&e| e == v), Some(v));
+        }
+        assert!(table.validate(id).is_empty());
+        table.set_incremental(false);
+        assert!(table.old_heads.is_empty());
+        assert!(table.validate(id).is_empty());
+        assert_eq!(table.len(), 533);
+    }
+
+    #[test]
+    fn resize_keeps_elements_in_place() {
+        let mut table = RawTable::new();
+        for v in 0..100u64 {
+            table.insert(v, v);
+        }
+        let (entries, hashes, next) = (table.entries.as_ptr(), table.hashes.as_ptr(), table.next.as_ptr());
+        let before = table.allocation_size();
+        table.resize();
+        // only the bucket heads are reallocated
+        assert_eq!(table.entries.as_ptr(), entries);
+        assert_eq!(table.hashes.as_ptr(), hashes);
+        assert_eq!(table.next.as_ptr(), next);
+        assert_eq!(table.allocation_size() - before, table.bucket_count() / 2 * 4);
+        assert!(table.validate(id).is_empty());
+    }
+
+    #[test]
+    fn shrink() {
+        let mut table = RawTable::new();
+        table.set_auto_shrink(true);
+        for v in 0..10_000u64 {
+            table.insert(v, v);
+        }
+        let peak = table.bucket_count();
+        for v in 0..9_900u64 {
+            table.remove(v, |&e| e == v);
+            assert!(table.len() >= table.bucket_count() / 8 || table.bucket_count() <= 64);
+        }
+        assert!(table.bucket_count() < peak / 16);
+        assert!(table.entries.capacity() < 1_000);
+        assert!(table.validate(id).is_empty());
+
+        table.set_auto_shrink(false);
+        for v in 9_900..9_990u64 {
+            table.remove(v, |&e| e == v);
+        }
+        assert_eq!(table.bucket_count(), 256);
+        table.shrink_to_fit();
+        assert_eq!(table.bucket_count(), 16);
+        assert!(table.validate(id).is_empty());
+    }
+
+    #[test]
+    fn max_load_factor() {
+        let mut sparse = RawTable::with_max_load_factor(0.25);
+        let mut dense = RawTable::with_max_load_factor(4.0);
+        for v in 0..1000u64 {
+            sparse.insert(v, v);
+            dense.insert(v, v);
+        }
+        assert_eq!(sparse.bucket_count(), 4096);
+        assert_eq!(dense.bucket_count(), 256);
+        assert!(dense.validate(id).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "max load factor")]
+    fn max_load_factor_range() {
+        RawTable::<u64>::with_max_load_factor(0.1);
+    }
+
+    #[test]
+    fn try_reserve() {
+        let mut table = RawTable::new();
+        assert!(table.try_reserve(usize::MAX).is_err());
+        assert!(table.try_reserve(usize::MAX / 16).is_err());
+        assert_eq!(table.bucket_count(), 0);
+        table.try_reserve(100).unwrap();
+        let buckets = table.bucket_count();
+        for v in 0..100u64 {
+            table.insert_no_grow(v, v);
+        }
+        assert_eq!(table.bucket_count(), buckets);
+
+        table.set_incremental(true);
+        table.try_reserve(100).unwrap();
+        assert!(table.validate(id).is_empty());
+    }
+
+    #[test]
+    fn growth_policy() {
+        use crate::growth::Doubling;
+
+        let capped = Doubling {
+            max_buckets: 64,
+            ..Doubling::default()
+        };
+        let mut table = RawTable::with_growth_policy(std::sync::Arc::new(capped));
+        for v in 0..1_000u64 {
+            table.insert(v, v);
+        }
+        assert_eq!(table.bucket_count(), 64);
+        table.resize();
+        assert_eq!(table.bucket_count(), 64);
+        assert!(table.validate(id).is_empty());
+
+        // the first bucket doesn't depend on the policy
+        struct Never;
+        impl crate::growth::GrowthPolicy for Never {
+            fn is_overloaded(&self, _: usize, _: usize) -> bool {
+                false
+            }
+            fn grow(&self, _: usize) -> Option<usize> {
+                None
+            }
+        }
+        let mut never = RawTable::with_growth_policy(std::sync::Arc::new(Never));
+        for v in 0..100u64 {
+            never.insert(v, v);
+        }
+        assert_eq!(never.bucket_count(), INITIAL_NBUCKET);
+        assert!((0..100u64).all(|v| never.find(v, |&e| e == v).is_some()));
+
+        // the default policy spelled out grows exactly like no policy
+        let mut default = RawTable::new();
+        let mut spelled_out = RawTable::with_growth_policy(std::sync::Arc::new(Doubling::default()));
+        for v in 0..1_000u64 {
+            default.insert(v, v);
+            spelled_out.insert(v, v);
+            assert_eq!(default.bucket_count(), spelled_out.bucket_count());
+        }
+    }
+
+    #[test]
+    fn zero_sized_values() {
+        use std::mem::size_of;
+        let mut table = RawTable::new();
+        for v in 0..100u64 {
+            table.insert(v, (v, ()));
+        }
+        // a set pays for its keys, their hashes and links, nothing more
+        let per_element = 2 * size_of::<u64>() + size_of::<u32>();
+        assert_eq!(
+            table.allocation_size(),
+            table.entries.capacity() * per_element + table.bucket_count() * size_of::<u32>()
+        );
+    }
+
+    #[test]
+    fn buckets() {
+        let mut table = RawTable::new();
+        // everything collides
+        for v in 0..4u64 {
+            table.insert(0, v);
+        }
+        let sizes: Vec<usize> = (0..table.bucket_count())
+            .map(|b| table.iter_bucket(b).count())
+            .collect();
+        assert_eq!(sizes.iter().sum::<usize>(), 4);
+        assert_eq!(sizes[0], 4);
+
+        // unlinking from the middle, the head and the tail of a chain
+        let slot = table.find(0, |&v| v == 2).unwrap();
+        table.erase(slot);
+        table.pop();
+        let head = table.find(0, |_| true).unwrap();
+        table.erase(head);
+        assert_eq!(table.iter_bucket(0).count(), 1);
+        assert!(table.validate(|_| 0).is_empty());
+    }
+}
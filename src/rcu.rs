@@ -0,0 +1,190 @@
+//! a map for read-mostly workloads: readers use immutable snapshots
+//!
+//! Writers copy the current `Hashmap`, change the copy and publish it as
+//! the new snapshot. A `Reader` keeps the snapshot it last saw and only
+//! checks an atomic version counter on each lookup; it touches a lock just
+//! once after every publish, to pick up the new snapshot. Writes cost a
+//! full copy of the map, so this only pays off when they are rare.
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+};
+
+use crate::{DefaultHashBuilder, Hashmap};
+
+pub struct RcuMap<K, V, S = DefaultHashBuilder> {
+    current: Mutex<Arc<Hashmap<K, V, S>>>,
+    version: AtomicU64,
+    // serializes writers, so copying never happens under `current`
+    writer: Mutex<()>,
+}
+
+impl<K, V> RcuMap<K, V> {
+    pub fn new() -> Self {
+        Self::from_map(Hashmap::new())
+    }
+}
+
+impl<K, V> Default for RcuMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, S> RcuMap<K, V, S> {
+    pub fn from_map(map: Hashmap<K, V, S>) -> Self {
+        RcuMap {
+            current: Mutex::new(Arc::new(map)),
+            version: AtomicU64::new(0),
+            writer: Mutex::new(()),
+        }
+    }
+
+    /// The current snapshot; it never changes, later writes publish new
+    /// ones.
+    pub fn load(&self) -> Arc<Hashmap<K, V, S>> {
+        let current = self.current.lock().unwrap_or_else(PoisonError::into_inner);
+        Arc::clone(&current)
+    }
+
+    pub fn reader(&self) -> Reader<'_, K, V, S> {
+        let version = self.version.load(Ordering::Acquire);
+        Reader {
+            map: self,
+            snapshot: self.load(),
+            version,
+        }
+    }
+}
+
+impl<K, V, S> RcuMap<K, V, S>
+where
+    K: Clone,
+    V: Clone,
+    S: Clone,
+{
+    /// Applies `f` to a copy of the current map and publishes the result.
+    pub fn update<R>(&self, f: impl FnOnce(&mut Hashmap<K, V, S>) -> R) -> R {
+        let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut next = Hashmap::clone(&self.load());
+        let result = f(&mut next);
+
+        let mut current = self.current.lock().unwrap_or_else(PoisonError::into_inner);
+        *current = Arc::new(next);
+        // bumped under the lock: a reader seeing the new version is sure to
+        // get the new snapshot
+        self.version.fetch_add(1, Ordering::Release);
+        result
+    }
+}
+
+impl<K, V, S> RcuMap<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.update(|map| map.insert(key, value))
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.update(|map| map.remove(key))
+    }
+}
+
+/// A per-thread handle caching the last snapshot it saw.
+pub struct Reader<'a, K, V, S = DefaultHashBuilder> {
+    map: &'a RcuMap<K, V, S>,
+    snapshot: Arc<Hashmap<K, V, S>>,
+    version: u64,
+}
+
+impl<K, V, S> Reader<'_, K, V, S> {
+    /// The latest snapshot, reloaded only if a writer published since the
+    /// last call.
+    pub fn snapshot(&mut self) -> &Hashmap<K, V, S> {
+        let version = self.map.version.load(Ordering::Acquire);
+        if version != self.version {
+            self.snapshot = self.map.load();
+            self.version = version;
+        }
+        &self.snapshot
+    }
+}
+
+impl<K, V, S> Reader<'_, K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.snapshot().get(key)
+    }
+
+    pub fn contains_key<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.snapshot().contains_key(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn readers_see_writes() {
+        let map = RcuMap::new();
+        map.insert("foo", 1);
+        let mut reader = map.reader();
+        assert_eq!(reader.get("foo"), Some(&1));
+
+        let old = map.load();
+        map.insert("foo", 2);
+        map.insert("bar", 3);
+        assert_eq!(reader.get("foo"), Some(&2));
+        assert_eq!(reader.get("bar"), Some(&3));
+        // snapshots are immutable
+        assert_eq!(old.get("foo"), Some(&1));
+        assert_eq!(map.remove("bar"), Some(3));
+        assert!(!reader.contains_key("bar"));
+    }
+
+    #[test]
+    fn concurrent_readers() {
+        let map = RcuMap::new();
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let mut reader = map.reader();
+                    let mut last = 0;
+                    while last < 100 {
+                        // values only ever grow
+                        let now = reader.get(&0).copied().unwrap_or(0);
+                        assert!(now >= last);
+                        last = now;
+                    }
+                });
+            }
+            for i in 1..=100 {
+                map.insert(0, i);
+            }
+        });
+    }
+}
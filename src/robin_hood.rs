@@ -0,0 +1,334 @@
+//! an open-addressing table with Robin Hood probing
+//!
+//! Elements sit directly in one slot array, no chains: each goes to the
+//! first free slot at or after its home slot (picked by the hash's low
+//! bits). Inserting evicts any element closer to its own home than the
+//! newcomer is to its home, which keeps probe lengths even. Removal
+//! shifts the following elements back instead of leaving tombstones, so
+//! a lookup stops at the first empty slot or at an element closer to home
+//! than it has walked.
+//!
+//! `RobinHoodMap` is a `Hashmap` on `RobinHoodTable`.
+use std::{iter::Flatten, mem, slice};
+
+use crate::TableBackend;
+
+const INITIAL_CAPACITY: usize = 8;
+
+/// Position of an element inside a `RobinHoodTable`.
+///
+/// A slot stays valid until the table is modified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Slot {
+    index: usize,
+}
+
+#[derive(Clone)]
+struct Bucket<T> {
+    hash: u64,
+    value: T,
+}
+
+/// Iterator over a `RobinHoodTable`'s elements, from `RobinHoodTable::iter`.
+pub struct Iter<'a, T> {
+    inner: Flatten<slice::Iter<'a, Option<Bucket<T>>>>,
+}
+
+#[derive(Clone)]
+pub struct RobinHoodTable<T> {
+    // a power of two long, or empty
+    slots: Vec<Option<Bucket<T>>>,
+    items: usize,
+}
+
+impl<T> RobinHoodTable<T> {
+    pub const fn new() -> Self {
+        RobinHoodTable {
+            slots: Vec::new(),
+            items: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+
+    /// number of slots, always zero or a power of two
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn mask(&self) -> usize {
+        self.slots.len() - 1
+    }
+
+    /// how far slot `index` is from `hash`'s home slot
+    fn distance(&self, hash: u64, index: usize) -> usize {
+        index.wrapping_sub(hash as usize) & self.mask()
+    }
+
+    pub fn find(&self, hash: u64, eq: impl FnMut(&T) -> bool) -> Option<Slot> {
+        self.find_probed(hash, eq).0
+    }
+
+    /// `find`, also counting the occupied slots it looked at
+    pub fn find_probed(&self, hash: u64, mut eq: impl FnMut(&T) -> bool) -> (Option<Slot>, usize) {
+        if self.slots.is_empty() {
+            return (None, 0);
+        }
+        let mut index = hash as usize & self.mask();
+        let mut walked = 0;
+        while let Some(bucket) = &self.slots[index] {
+            // anything this close to home would have evicted us
+            if self.distance(bucket.hash, index) < walked {
+                return (None, walked + 1);
+            }
+            if bucket.hash == hash && eq(&bucket.value) {
+                return (Some(Slot { index }), walked + 1);
+            }
+            index = (index + 1) & self.mask();
+            walked += 1;
+        }
+        (None, walked)
+    }
+
+    /// # Panics
+    ///
+    /// if `slot` doesn't point at an element (e.g. it is stale)
+    pub fn slot(&self, slot: Slot) -> &T {
+        &self.slots[slot.index].as_ref().expect("stale slot").value
+    }
+
+    /// # Panics
+    ///
+    /// if `slot` doesn't point at an element (e.g. it is stale)
+    pub fn slot_mut(&mut self, slot: Slot) -> &mut T {
+        &mut self.slots[slot.index].as_mut().expect("stale slot").value
+    }
+
+    /// Makes room for `additional` more elements, growing if the load
+    /// factor would go above 7/8.
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = self.items + additional;
+        if needed <= self.slots.len() / 8 * 7 {
+            return;
+        }
+        let mut capacity = self.slots.len().max(INITIAL_CAPACITY);
+        while needed > capacity / 8 * 7 {
+            capacity *= 2;
+        }
+        self.resize(capacity);
+    }
+
+    fn resize(&mut self, capacity: usize) {
+        let old = mem::replace(&mut self.slots, (0..capacity).map(|_| None).collect());
+        self.items = 0;
+        for bucket in old.into_iter().flatten() {
+            self.insert_no_grow(bucket.hash, bucket.value);
+        }
+    }
+
+    /// Inserts `value` under `hash` without checking for an equal element;
+    /// callers wanting map semantics must `find` first.
+    pub fn insert(&mut self, hash: u64, value: T) -> Slot {
+        self.reserve(1);
+        self.insert_no_grow(hash, value)
+    }
+
+    /// Like `insert`, for callers that already called `reserve`.
+    ///
+    /// # Panics
+    ///
+    /// if the table is full
+    pub fn insert_no_grow(&mut self, hash: u64, value: T) -> Slot {
+        assert!(self.items < self.slots.len(), "RobinHoodTable is full");
+        let mask = self.mask();
+        let mut carried = Bucket { hash, value };
+        let mut index = hash as usize & mask;
+        let mut walked = 0;
+        // where `value` itself ends up, once something evicts it or it
+        // finds a free slot
+        let mut placed = None;
+        loop {
+            match &mut self.slots[index] {
+                empty @ None => {
+                    *empty = Some(carried);
+                    self.items += 1;
+                    return Slot {
+                        index: placed.unwrap_or(index),
+                    };
+                }
+                Some(bucket) => {
+                    let theirs = index.wrapping_sub(bucket.hash as usize) & mask;
+                    if theirs < walked {
+                        // take from the rich: carry the evicted one on
+                        mem::swap(bucket, &mut carried);
+                        placed.get_or_insert(index);
+                        walked = theirs;
+                    }
+                }
+            }
+            index = (index + 1) & mask;
+            walked += 1;
+        }
+    }
+
+    /// Removes the element at `slot`, shifting the elements after it
+    /// back; that invalidates their slots.
+    pub fn erase(&mut self, slot: Slot) -> T {
+        let removed = self.slots[slot.index].take().expect("stale slot");
+        self.items -= 1;
+        let mut hole = slot.index;
+        loop {
+            let next = (hole + 1) & self.mask();
+            match &self.slots[next] {
+                Some(bucket) if self.distance(bucket.hash, next) > 0 => {
+                    self.slots[hole] = self.slots[next].take();
+                    hole = next;
+                }
+                _ => break,
+            }
+        }
+        removed.value
+    }
+
+    pub fn remove(&mut self, hash: u64, eq: impl FnMut(&T) -> bool) -> Option<T> {
+        let slot = self.find(hash, eq)?;
+        Some(self.erase(slot))
+    }
+
+    /// the longest distance any element sits from its home slot
+    pub fn max_probe_distance(&self) -> usize {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, bucket)| Some(self.distance(bucket.as_ref()?.hash, index)))
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            inner: self.slots.iter().flatten(),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> + '_ {
+        self.slots.iter_mut().flatten().map(|bucket| &mut bucket.value)
+    }
+}
+
+impl<T> Default for RobinHoodTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        self.inner.next().map(|bucket| &bucket.value)
+    }
+}
+
+impl<K, V> TableBackend<K, V> for RobinHoodTable<(K, V)> {
+    type Slot = Slot;
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn bucket_count(&self) -> usize {
+        self.capacity()
+    }
+
+    fn find(&self, hash: u64, eq: impl FnMut(&(K, V)) -> bool) -> Option<Slot> {
+        self.find(hash, eq)
+    }
+
+    fn find_probed(&self, hash: u64, eq: impl FnMut(&(K, V)) -> bool) -> (Option<Slot>, usize) {
+        self.find_probed(hash, eq)
+    }
+
+    fn slot(&self, slot: Slot) -> &(K, V) {
+        self.slot(slot)
+    }
+
+    fn slot_mut(&mut self, slot: Slot) -> &mut (K, V) {
+        self.slot_mut(slot)
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.reserve(additional)
+    }
+
+    fn insert_no_grow(&mut self, hash: u64, entry: (K, V)) -> Slot {
+        self.insert_no_grow(hash, entry)
+    }
+
+    fn erase(&mut self, slot: Slot) -> (K, V) {
+        self.erase(slot)
+    }
+
+    fn resize(&mut self) {
+        self.resize((self.capacity() * 2).max(INITIAL_CAPACITY))
+    }
+
+    fn next_run(&self, pos: &mut usize) -> &[(K, V)] {
+        for (index, bucket) in self.slots.iter().enumerate().skip(*pos) {
+            if let Some(bucket) = bucket {
+                *pos = index + 1;
+                return std::slice::from_ref(&bucket.value);
+            }
+        }
+        *pos = self.slots.len();
+        &[]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table() {
+        let mut table = RobinHoodTable::new();
+        // everything wants slot 0 or 1
+        for v in 0..20u64 {
+            let slot = table.insert(v % 2, v);
+            assert_eq!(*table.slot(slot), v);
+        }
+        for v in 0..20u64 {
+            assert_eq!(table.find(v % 2, |&e| e == v).map(|s| *table.slot(s)), Some(v));
+        }
+        assert_eq!(table.remove(0, |&e| e == 4), Some(4));
+        assert_eq!(table.remove(0, |&e| e == 4), None);
+        for v in (0..20u64).filter(|&v| v != 4) {
+            assert!(table.find(v % 2, |&e| e == v).is_some());
+        }
+        assert_eq!(table.len(), 19);
+        assert_eq!(table.iter().count(), 19);
+    }
+
+    #[test]
+    fn map() {
+        let mut map: crate::RobinHoodMap<_, _> = crate::RobinHoodMap::default();
+        for i in 0..10_000u32 {
+            assert_eq!(map.insert(i, i * 2), None);
+        }
+        assert_eq!(map.insert(7, 0), Some(14));
+        for i in (0..10_000).step_by(2) {
+            assert!(map.remove(&i).is_some());
+        }
+        assert_eq!(map.len(), 5_000);
+        assert_eq!(map.get(&7), Some(&0));
+        assert_eq!(map.get(&8), None);
+        *map.get_mut(&9).unwrap() += 1;
+        assert_eq!(map.get(&9), Some(&19));
+        assert!(map.table.max_probe_distance() < 64);
+    }
+}
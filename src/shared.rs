@@ -0,0 +1,69 @@
+//! one hasher for many maps
+//!
+//! Two maps built from the same `SharedHasher` hash every key to the same
+//! value, seed included, so a key lands on the same shard everywhere and
+//! entries moved between the maps hash the same on both sides.
+use std::{
+    hash::{BuildHasher, RandomState},
+    sync::Arc,
+};
+
+/// A `BuildHasher` behind an `Arc`: clones share it instead of copying it,
+/// whatever `S` is.
+#[derive(Debug, Default)]
+pub struct SharedHasher<S = RandomState>(Arc<S>);
+
+impl<S> SharedHasher<S> {
+    pub fn new(hash_builder: S) -> Self {
+        SharedHasher(Arc::new(hash_builder))
+    }
+
+    /// whether `self` and `other` are clones of each other
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<S> Clone for SharedHasher<S> {
+    fn clone(&self) -> Self {
+        SharedHasher(Arc::clone(&self.0))
+    }
+}
+
+impl<S> From<Arc<S>> for SharedHasher<S> {
+    fn from(hash_builder: Arc<S>) -> Self {
+        SharedHasher(hash_builder)
+    }
+}
+
+impl<S: BuildHasher> BuildHasher for SharedHasher<S> {
+    type Hasher = S::Hasher;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        self.0.build_hasher()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Hashmap;
+
+    #[test]
+    fn same_hashes() {
+        let hasher = SharedHasher::new(RandomState::new());
+        let mut a = Hashmap::with_hasher(hasher.clone());
+        let mut b = Hashmap::with_hasher(hasher.clone());
+        assert!(a.hasher().ptr_eq(b.hasher()));
+        for i in 0..100 {
+            a.insert(i, i);
+        }
+        b.reserve(100);
+        for (key, value) in a {
+            assert_eq!(hasher.hash_one(key), b.hasher().hash_one(key));
+            b.insert(key, value);
+        }
+        assert_eq!(b.len(), 100);
+        assert!(!hasher.ptr_eq(&SharedHasher::new(RandomState::new())));
+    }
+}
@@ -0,0 +1,208 @@
+//! a map that skips hashing while it is tiny
+//!
+//! Up to `INLINE_LIMIT` entries, `SmallMap` keeps its entries in a flat
+//! array and finds keys by comparing them one by one, which for a handful
+//! of keys is cheaper than hashing even one. A new key past the limit
+//! moves everything into a `Hashmap`, which it keeps using until it is
+//! emptied again. `AutoMap` is the same map, for callers who'd rather
+//! not pick a representation at all.
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+    mem,
+};
+
+use crate::{DefaultHashBuilder, Hashmap};
+
+/// entries kept unhashed
+pub const INLINE_LIMIT: usize = 8;
+
+/// A map that is whichever of a flat array and a hash table suits its
+/// size; see the module docs.
+pub type AutoMap<K, V, S = DefaultHashBuilder> = SmallMap<K, V, S>;
+
+#[derive(Clone)]
+pub struct SmallMap<K, V, S = DefaultHashBuilder> {
+    // at most one of these is non-empty
+    inline: Vec<(K, V)>,
+    map: Hashmap<K, V, S>,
+}
+
+impl<K, V> SmallMap<K, V> {
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V> Default for SmallMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, S> SmallMap<K, V, S> {
+    pub fn with_hasher(hash_builder: S) -> Self {
+        SmallMap {
+            inline: Vec::new(),
+            map: Hashmap::with_hasher(hash_builder),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inline.len() + self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// whether entries have moved into the hashed map
+    pub fn is_spilled(&self) -> bool {
+        !self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.inline.iter().map(|(key, val)| (key, val)).chain(&self.map)
+    }
+}
+
+impl<K, V, S> SmallMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    fn position<Q>(&self, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.inline.iter().position(|(ekey, _)| ekey.borrow() == key)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if self.is_spilled() {
+            return self.map.insert(key, value);
+        }
+        if let Some(i) = self.position(&key) {
+            return Some(mem::replace(&mut self.inline[i].1, value));
+        }
+        if self.inline.len() < INLINE_LIMIT {
+            self.inline.reserve_exact(INLINE_LIMIT - self.inline.len());
+            self.inline.push((key, value));
+        } else {
+            self.map.reserve(INLINE_LIMIT + 1);
+            self.map.extend(self.inline.drain(..));
+            self.map.insert(key, value);
+        }
+        None
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        if self.is_spilled() {
+            return self.map.get(key);
+        }
+        self.position(key).map(|i| &self.inline[i].1)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        if self.is_spilled() {
+            return self.map.get_mut(key);
+        }
+        self.position(key).map(|i| &mut self.inline[i].1)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        if self.is_spilled() {
+            return self.map.remove(key);
+        }
+        let i = self.position(key)?;
+        Some(self.inline.swap_remove(i).1)
+    }
+}
+
+impl<K, V, S> Extend<(K, V)> for SmallMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K, V, S> FromIterator<(K, V)> for SmallMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::with_hasher(S::default());
+        map.extend(iter);
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spill() {
+        let mut map = SmallMap::new();
+        for i in 0..INLINE_LIMIT {
+            assert_eq!(map.insert(i, i), None);
+        }
+        assert_eq!(map.insert(0, 10), Some(0));
+        assert!(!map.is_spilled());
+        assert_eq!(map.remove(&1), Some(1));
+        assert_eq!(map.insert(1, 1), None);
+
+        assert_eq!(map.insert(INLINE_LIMIT, 0), None);
+        assert!(map.is_spilled());
+        assert_eq!(map.len(), INLINE_LIMIT + 1);
+        assert_eq!(map.get(&0), Some(&10));
+        *map.get_mut(&1).unwrap() += 1;
+        assert_eq!(map.get(&1), Some(&2));
+        assert_eq!(map.iter().count(), INLINE_LIMIT + 1);
+
+        for i in 0..=INLINE_LIMIT {
+            assert!(map.remove(&i).is_some());
+        }
+        // empty again, so back to scanning
+        map.insert(3, 3);
+        assert!(!map.is_spilled());
+        assert!(map.contains_key(&3));
+    }
+
+    #[test]
+    fn auto_map() {
+        let small: AutoMap<_, _> = (0..3).map(|i| (i, i)).collect();
+        assert!(!small.is_spilled());
+        let large: AutoMap<_, _> = (0..100).map(|i| (i, i)).collect();
+        assert!(large.is_spilled());
+        assert_eq!(large.len(), 100);
+        assert_eq!(large.get(&99), Some(&99));
+    }
+}
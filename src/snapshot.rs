@@ -0,0 +1,188 @@
+//! versioned binary snapshots of a whole `Hashmap`
+//!
+//! Format (all integers little-endian):
+//!
+//! ```text
+//! magic "RHSNAP", version: u16, items: u64,
+//! items x (key_len: u32, key, value_len: u32, value),
+//! crc32 of everything before it: u32
+//! ```
+//!
+//! Keys and values go through `codec::Codec`, no serde involved.
+use std::{
+    hash::{BuildHasher, Hash},
+    io::{self, Read, Write},
+};
+
+use crate::{
+    codec::{Codec, Crc32},
+    Hashmap,
+};
+
+const MAGIC: &[u8; 6] = b"RHSNAP";
+const VERSION: u16 = 1;
+// don't trust the item count of an unverified snapshot with a huge allocation
+const MAX_PRESIZE: usize = 1 << 16;
+
+fn invalid(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+struct CrcWriter<W> {
+    inner: W,
+    crc: Crc32,
+}
+
+impl<W: Write> CrcWriter<W> {
+    fn put(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.crc.update(bytes);
+        self.inner.write_all(bytes)
+    }
+
+    fn put_chunk(&mut self, chunk: &[u8]) -> io::Result<()> {
+        let len = u32::try_from(chunk.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "entry too large"))?;
+        self.put(&len.to_le_bytes())?;
+        self.put(chunk)
+    }
+}
+
+struct CrcReader<R> {
+    inner: R,
+    crc: Crc32,
+}
+
+impl<R: Read> CrcReader<R> {
+    fn take(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.inner.read_exact(buf)?;
+        self.crc.update(buf);
+        Ok(())
+    }
+
+    fn take_u32(&mut self) -> io::Result<u32> {
+        let mut buf = [0; 4];
+        self.take(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn take_chunk(&mut self, buf: &mut Vec<u8>) -> io::Result<()> {
+        let len = self.take_u32()? as usize;
+        buf.clear();
+        // read through `take` so a bogus length fails on EOF, not on allocation
+        (&mut self.inner).take(len as u64).read_to_end(buf)?;
+        if buf.len() != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.crc.update(buf);
+        Ok(())
+    }
+}
+
+impl<K, V, S> Hashmap<K, V, S>
+where
+    K: Eq + Hash + Codec,
+    V: Codec,
+    S: BuildHasher + Default,
+{
+    /// Writes every entry to `out`; wrap it in a `BufWriter` for files.
+    pub fn write_snapshot<W: Write>(&self, out: W) -> io::Result<()> {
+        let mut out = CrcWriter {
+            inner: out,
+            crc: Crc32::new(),
+        };
+        out.put(MAGIC)?;
+        out.put(&VERSION.to_le_bytes())?;
+        out.put(&(self.len() as u64).to_le_bytes())?;
+
+        let mut buf = Vec::new();
+        for (key, value) in self {
+            buf.clear();
+            key.encode(&mut buf);
+            out.put_chunk(&buf)?;
+            buf.clear();
+            value.encode(&mut buf);
+            out.put_chunk(&buf)?;
+        }
+        let crc = out.crc.finish();
+        out.inner.write_all(&crc.to_le_bytes())?;
+        out.inner.flush()
+    }
+
+    /// Reads a snapshot written by `write_snapshot`, failing on a version
+    /// mismatch, truncation or checksum error.
+    pub fn read_snapshot<R: Read>(input: R) -> io::Result<Self> {
+        let mut input = CrcReader {
+            inner: input,
+            crc: Crc32::new(),
+        };
+        let mut magic = [0; 6];
+        input.take(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a snapshot"));
+        }
+        let mut version = [0; 2];
+        input.take(&mut version)?;
+        if u16::from_le_bytes(version) != VERSION {
+            return Err(invalid("unsupported snapshot version"));
+        }
+        let mut items = [0; 8];
+        input.take(&mut items)?;
+        let items = u64::from_le_bytes(items);
+
+        let mut map = Self::default();
+        let presize = usize::try_from(items).unwrap_or(MAX_PRESIZE).min(MAX_PRESIZE);
+        map.table.reserve(presize);
+        let (mut key, mut value) = (Vec::new(), Vec::new());
+        for _ in 0..items {
+            input.take_chunk(&mut key)?;
+            input.take_chunk(&mut value)?;
+            map.insert(
+                K::decode(&key).map_err(invalid)?,
+                V::decode(&value).map_err(invalid)?,
+            );
+        }
+
+        let expected = input.crc.finish();
+        let mut crc = [0; 4];
+        input.inner.read_exact(&mut crc)?;
+        if u32::from_le_bytes(crc) != expected {
+            return Err(invalid("snapshot checksum mismatch"));
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut map = Hashmap::new();
+        for i in 0..50u64 {
+            map.insert(format!("key{}", i), i);
+        }
+        let mut buf = Vec::new();
+        map.write_snapshot(&mut buf).unwrap();
+
+        let restored = Hashmap::<String, u64>::read_snapshot(&buf[..]).unwrap();
+        assert_eq!(restored.len(), 50);
+        assert_eq!(restored.get("key7"), Some(&7));
+    }
+
+    #[test]
+    fn corruption() {
+        let mut map = Hashmap::new();
+        map.insert(1u32, String::from("one"));
+        let mut buf = Vec::new();
+        map.write_snapshot(&mut buf).unwrap();
+
+        let mut flipped = buf.clone();
+        flipped[20] ^= 1;
+        assert!(Hashmap::<u32, String>::read_snapshot(&flipped[..]).is_err());
+        assert!(Hashmap::<u32, String>::read_snapshot(&buf[..buf.len() - 1]).is_err());
+        let mut future = buf.clone();
+        future[6] = 2;
+        assert!(Hashmap::<u32, String>::read_snapshot(&future[..]).is_err());
+    }
+}
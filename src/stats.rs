@@ -0,0 +1,354 @@
+//! occupancy and memory statistics, for choosing hashers and capacities
+//! with data
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+};
+
+use crate::{hash_entry, make_hash, raw::Violation, Hashmap};
+
+/// A summary of how a map's entries are spread over its buckets.
+///
+/// Chains are the per-bucket collision lists; a lookup compares the key
+/// against the chain's entries in order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub len: usize,
+    pub bucket_count: usize,
+    /// entries per bucket
+    pub load_factor: f64,
+    /// average length of the non-empty chains
+    pub avg_chain_len: f64,
+    pub max_chain_len: usize,
+    /// fraction of buckets holding no entry
+    pub empty_bucket_ratio: f64,
+    /// average number of key comparisons a successful lookup makes
+    pub avg_probe_len: f64,
+}
+
+/// One bucket in `Hashmap::worst_buckets`; its `Debug` output lists the
+/// keys when they implement `Debug`.
+#[derive(Debug, Clone)]
+pub struct BucketReport<'a, K> {
+    pub bucket: usize,
+    pub keys: Vec<&'a K>,
+}
+
+impl<K> BucketReport<'_, K> {
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+impl<K, V, S> Hashmap<K, V, S>
+where
+    K: Hash,
+    S: BuildHasher,
+{
+    /// Verifies the table's internal invariants (item count, bucket count,
+    /// stored hashes, every entry in the bucket its hash picks), for
+    /// debugging the crate or a suspected corruption. O(len), rehashes
+    /// every key.
+    pub fn debug_validate(&self) -> Result<(), Vec<Violation>> {
+        let violations = self.table.validate(hash_entry(&self.hash_builder));
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// The bucket `key` is (or would be) in, for `iter_bucket`. While
+    /// growing incrementally, that is its bucket in the grown table.
+    ///
+    /// # Panics
+    ///
+    /// if the map has no buckets yet
+    pub fn bucket_of<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.table.bucket_of(make_hash(&self.hash_builder, key))
+    }
+}
+
+fn ratio(num: usize, den: usize) -> f64 {
+    if den == 0 {
+        0.0
+    } else {
+        num as f64 / den as f64
+    }
+}
+
+impl<K, V, S> Hashmap<K, V, S> {
+    /// Walks every bucket, so this is O(capacity).
+    pub fn stats(&self) -> Stats {
+        let bucket_count = self.table.bucket_count();
+        let (mut empty, mut max_chain_len, mut probes) = (0, 0, 0);
+        for bucket in 0..bucket_count {
+            let chain = self.table.iter_bucket(bucket).count();
+            if chain == 0 {
+                empty += 1;
+            }
+            max_chain_len = max_chain_len.max(chain);
+            // the i-th entry of a chain is found after i comparisons
+            probes += chain * (chain + 1) / 2;
+        }
+        let len = self.len();
+        Stats {
+            len,
+            bucket_count,
+            load_factor: ratio(len, bucket_count),
+            avg_chain_len: ratio(len, bucket_count - empty),
+            max_chain_len,
+            empty_bucket_ratio: ratio(empty, bucket_count),
+            avg_probe_len: ratio(probes, len),
+        }
+    }
+
+    /// Heap bytes held by the map's table, counting allocated capacity
+    /// rather than just what is in use.
+    ///
+    /// Heap memory owned by keys and values (a `String`'s buffer, ...)
+    /// isn't seen; use `memory_usage_with` to add it.
+    pub fn memory_usage(&self) -> usize {
+        self.table.allocation_size()
+    }
+
+    /// `memory_usage` plus `entry_heap_size` summed over every entry.
+    pub fn memory_usage_with(&self, mut entry_heap_size: impl FnMut(&K, &V) -> usize) -> usize {
+        let entries: usize = self.into_iter().map(|(key, value)| entry_heap_size(key, value)).sum();
+        self.memory_usage() + entries
+    }
+
+    /// `histogram[n]` is the number of buckets holding exactly `n` entries.
+    pub fn chain_histogram(&self) -> Vec<usize> {
+        let mut histogram = Vec::new();
+        for bucket in 0..self.table.bucket_count() {
+            let chain = self.table.iter_bucket(bucket).count();
+            if histogram.len() <= chain {
+                histogram.resize(chain + 1, 0);
+            }
+            histogram[chain] += 1;
+        }
+        histogram
+    }
+
+    /// Bucket `index`'s entries, in the order a lookup compares them.
+    ///
+    /// # Panics
+    ///
+    /// if `index >= self.bucket_count()`
+    pub fn iter_bucket(&self, index: usize) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.table.iter_bucket(index).map(|(key, value)| (key, value))
+    }
+
+    /// The `n` longest buckets, longest first, with the keys in each.
+    pub fn worst_buckets(&self, n: usize) -> Vec<BucketReport<'_, K>> {
+        let mut buckets: Vec<_> = (0..self.table.bucket_count())
+            .map(|bucket| (self.table.iter_bucket(bucket).count(), bucket))
+            .filter(|&(chain, _)| chain > 0)
+            .collect();
+        // longest first, ties in bucket order
+        buckets.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        buckets
+            .into_iter()
+            .take(n)
+            .map(|(_, bucket)| BucketReport {
+                bucket,
+                keys: self.table.iter_bucket(bucket).map(|(key, _)| key).collect(),
+            })
+            .collect()
+    }
+
+    /// A picture of the buckets, one character per bucket: `.` for
+    /// empty, `1`-`9` for a chain of that length, `#` for longer. Past
+    /// `LAYOUT_CELLS` buckets, each character stands for a run of buckets
+    /// and shows the longest chain among them. Rows of 64, each starting
+    /// with its first bucket's index, and the `Stats` summary last.
+    ///
+    /// Only with the `layout` feature.
+    #[cfg(feature = "layout")]
+    pub fn dump_layout(&self) -> String {
+        use std::fmt::Write;
+
+        let buckets = self.table.bucket_count();
+        let per_cell = buckets.div_ceil(LAYOUT_CELLS).max(1);
+        let mut out = String::new();
+        for (cell, first) in (0..buckets).step_by(per_cell).enumerate() {
+            if cell % 64 == 0 {
+                if cell > 0 {
+                    out.push('\n');
+                }
+                let _ = write!(out, "{:>8} ", first);
+            }
+            let longest = (first..(first + per_cell).min(buckets))
+                .map(|bucket| self.table.iter_bucket(bucket).count())
+                .max()
+                .unwrap_or(0);
+            out.push(match longest {
+                0 => '.',
+                1..=9 => char::from(b'0' + longest as u8),
+                _ => '#',
+            });
+        }
+        let stats = self.stats();
+        let _ = write!(
+            out,
+            "{}{} entries in {} buckets ({} per cell), longest chain {}, {:.0}% empty",
+            if buckets > 0 { "\n" } else { "" },
+            stats.len,
+            stats.bucket_count,
+            per_cell,
+            stats.max_chain_len,
+            stats.empty_bucket_ratio * 100.0,
+        );
+        out
+    }
+}
+
+/// the most characters `Hashmap::dump_layout` draws buckets with
+#[cfg(feature = "layout")]
+pub const LAYOUT_CELLS: usize = 4096;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats() {
+        let empty = Hashmap::<u32, u32>::new().stats();
+        assert_eq!(empty.bucket_count, 0);
+        assert_eq!(empty.load_factor, 0.0);
+        assert_eq!(empty.max_chain_len, 0);
+
+        let mut map = Hashmap::new();
+        for i in 0..1000u32 {
+            map.insert(i, i);
+        }
+        let stats = map.stats();
+        assert_eq!(stats.len, 1000);
+        assert_eq!(stats.load_factor, 1000.0 / stats.bucket_count as f64);
+        assert!(stats.max_chain_len >= 1);
+        assert!(stats.avg_chain_len >= 1.0 && stats.avg_chain_len <= stats.max_chain_len as f64);
+        assert!(stats.avg_probe_len >= 1.0);
+        assert!((0.0..1.0).contains(&stats.empty_bucket_ratio));
+    }
+
+    #[test]
+    fn buckets() {
+        let mut map = Hashmap::new();
+        for i in 0..200u32 {
+            map.insert(i, i * 2);
+        }
+        for i in 0..200u32 {
+            let bucket = map.bucket_of(&i);
+            assert!(map.iter_bucket(bucket).any(|(&k, &v)| k == i && v == i * 2));
+        }
+        let total: usize = (0..map.bucket_count()).map(|b| map.iter_bucket(b).count()).sum();
+        assert_eq!(total, 200);
+    }
+
+    #[test]
+    fn mid_migration() {
+        let mut map = Hashmap::new();
+        map.set_incremental_resize(true);
+        // leaves the last growth unfinished, see raw's `incremental` test
+        for i in 0..800u32 {
+            map.insert(i, i);
+        }
+        for i in 0..800u32 {
+            assert!(map.iter_bucket(map.bucket_of(&i)).any(|(&k, _)| k == i));
+        }
+        let histogram = map.chain_histogram();
+        assert_eq!(histogram.iter().sum::<usize>(), map.bucket_count());
+        let counted: usize = histogram.iter().enumerate().map(|(len, buckets)| len * buckets).sum();
+        assert_eq!(counted, 800);
+        let stats = map.stats();
+        assert_eq!(stats.max_chain_len, histogram.len() - 1);
+        assert!(stats.avg_probe_len >= 1.0);
+        let worst = map.worst_buckets(usize::MAX);
+        assert_eq!(worst.iter().map(|report| report.keys.len()).sum::<usize>(), 800);
+        assert_eq!(worst[0].keys.len(), stats.max_chain_len);
+    }
+
+    #[test]
+    #[cfg(feature = "layout")]
+    fn dump_layout() {
+        #[derive(Default)]
+        struct Constant;
+        impl std::hash::Hasher for Constant {
+            fn write(&mut self, _: &[u8]) {}
+            fn finish(&self) -> u64 {
+                0
+            }
+        }
+
+        let mut map = Hashmap::with_hasher(std::hash::BuildHasherDefault::<Constant>::default());
+        map.rehash_to(128);
+        for i in 0..10u64 {
+            map.insert(i, ());
+        }
+        let layout = map.dump_layout();
+        let rows: Vec<_> = layout.lines().collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], format!("       0 #{}", ".".repeat(63)));
+        assert_eq!(rows[1], format!("      64 {}", ".".repeat(64)));
+        assert!(rows[2].starts_with("10 entries in 128 buckets (1 per cell), longest chain 10"));
+
+        let mut big = Hashmap::new();
+        big.rehash_to(LAYOUT_CELLS * 4);
+        big.insert(0u64, ());
+        assert_eq!(big.dump_layout().lines().count(), LAYOUT_CELLS / 64 + 1);
+        assert!(Hashmap::<u64, ()>::new().dump_layout().starts_with("0 entries"));
+    }
+
+    #[test]
+    fn debug_validate() {
+        let mut map = Hashmap::new();
+        assert_eq!(map.debug_validate(), Ok(()));
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        map.remove(&50);
+        assert_eq!(map.debug_validate(), Ok(()));
+    }
+
+    #[test]
+    fn memory_usage() {
+        assert_eq!(Hashmap::<u64, u64>::new().memory_usage(), 0);
+        let mut map = Hashmap::new();
+        for i in 0..100u64 {
+            map.insert(i, i.to_string());
+        }
+        let table = map.memory_usage();
+        assert!(table >= 100 * std::mem::size_of::<(u64, String)>());
+        let strings: usize = (0..100u64).map(|i| i.to_string().capacity()).sum();
+        assert_eq!(map.memory_usage_with(|_, v| v.capacity()), table + strings);
+    }
+
+    #[test]
+    fn worst_buckets() {
+        let mut map = Hashmap::new();
+        for i in 0..200u32 {
+            map.insert(i, ());
+        }
+        let stats = map.stats();
+        let histogram = map.chain_histogram();
+        assert_eq!(histogram.iter().sum::<usize>(), stats.bucket_count);
+        assert_eq!(histogram.len(), stats.max_chain_len + 1);
+        assert_eq!(histogram.iter().enumerate().map(|(n, b)| n * b).sum::<usize>(), 200);
+
+        let worst = map.worst_buckets(3);
+        assert_eq!(worst.len(), 3);
+        assert_eq!(worst[0].len(), stats.max_chain_len);
+        assert!(worst.windows(2).all(|w| w[0].len() >= w[1].len()));
+        assert!(format!("{:?}", worst[0]).contains("keys"));
+        assert!(Hashmap::<u32, ()>::new().worst_buckets(3).is_empty());
+    }
+}
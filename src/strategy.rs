@@ -0,0 +1,188 @@
+//! maps that decide for themselves when two keys are the same
+//!
+//! `StrategyMap` hashes and compares keys through a `KeyStrategy` instead
+//! of the keys' own `Hash` and `Eq`, so e.g. HTTP header names can be
+//! looked up ignoring case without wrapping every key in a newtype.
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hasher},
+    marker::PhantomData,
+    mem,
+};
+
+use crate::{DefaultHashBuilder, Entry, Hashmap, OccupiedEntry, VacantEntry};
+
+/// How a `StrategyMap` hashes and compares keys.
+///
+/// Keys that are `eq` must hash the same. For lookups by a borrowed form
+/// `Q` of the key type `K`, `KeyStrategy<Q>` must agree with
+/// `KeyStrategy<K>` on `k.borrow()`.
+pub trait KeyStrategy<K: ?Sized> {
+    fn hash<H: Hasher>(&self, key: &K, state: &mut H);
+
+    fn eq(&self, a: &K, b: &K) -> bool;
+}
+
+/// Compares strings ignoring ASCII case.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsciiCaseInsensitive;
+
+impl<K: AsRef<str> + ?Sized> KeyStrategy<K> for AsciiCaseInsensitive {
+    fn hash<H: Hasher>(&self, key: &K, state: &mut H) {
+        for byte in key.as_ref().bytes() {
+            state.write_u8(byte.to_ascii_lowercase());
+        }
+        // like `str`, so "ab", "c" and "a", "bc" differ in a tuple
+        state.write_u8(0xff);
+    }
+
+    fn eq(&self, a: &K, b: &K) -> bool {
+        a.as_ref().eq_ignore_ascii_case(b.as_ref())
+    }
+}
+
+#[derive(Clone)]
+pub struct StrategyMap<K, V, C, S = DefaultHashBuilder> {
+    map: Hashmap<K, V, S>,
+    strategy: C,
+}
+
+impl<K, V, C> StrategyMap<K, V, C> {
+    pub fn with_strategy(strategy: C) -> Self {
+        Self::with_strategy_and_hasher(strategy, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, C, S> StrategyMap<K, V, C, S> {
+    pub fn with_strategy_and_hasher(strategy: C, hash_builder: S) -> Self {
+        StrategyMap {
+            map: Hashmap::with_hasher(hash_builder),
+            strategy,
+        }
+    }
+
+    pub fn strategy(&self) -> &C {
+        &self.strategy
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> crate::Iter<'_, K, V> {
+        (&self.map).into_iter()
+    }
+}
+
+impl<K, V, C, S> StrategyMap<K, V, C, S>
+where
+    C: KeyStrategy<K>,
+    S: BuildHasher,
+{
+    fn hash<Q: ?Sized>(&self, key: &Q) -> u64
+    where
+        C: KeyStrategy<Q>,
+    {
+        let mut state = self.map.hash_builder.build_hasher();
+        self.strategy.hash(key, &mut state);
+        state.finish()
+    }
+
+    fn find<Q: ?Sized>(&self, hash: u64, key: &Q) -> Option<crate::raw::Slot>
+    where
+        K: Borrow<Q>,
+        C: KeyStrategy<Q>,
+    {
+        self.map.table.find(hash, |(ekey, _)| self.strategy.eq(ekey.borrow(), key))
+    }
+
+    /// Inserts `value` under `key`; if an equivalent key is already there
+    /// it keeps its spelling and its value is replaced and returned.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.entry(key) {
+            Entry::Occupied(e) => Some(mem::replace(&mut e.element.1, value)),
+            Entry::Vacant(e) => {
+                e.insert(value);
+                None
+            }
+        }
+    }
+
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+        let hash = self.hash(&key);
+        match self.find(hash, &key) {
+            Some(slot) => Entry::Occupied(OccupiedEntry {
+                element: self.map.table.slot_mut(slot),
+            }),
+            None => Entry::Vacant(VacantEntry {
+                table: &mut self.map.table,
+                metrics: self.map.metrics.as_deref(),
+                hash,
+                key,
+                marker: PhantomData,
+            }),
+        }
+    }
+
+    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        C: KeyStrategy<Q>,
+    {
+        let slot = self.find(self.hash(key), key)?;
+        Some(&self.map.table.slot(slot).1)
+    }
+
+    pub fn get_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        C: KeyStrategy<Q>,
+    {
+        let slot = self.find(self.hash(key), key)?;
+        Some(&mut self.map.table.slot_mut(slot).1)
+    }
+
+    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        C: KeyStrategy<Q>,
+    {
+        self.get(key).is_some()
+    }
+
+    pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        C: KeyStrategy<Q>,
+    {
+        let slot = self.find(self.hash(key), key)?;
+        Some(self.map.table.erase(slot).1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn case_insensitive() {
+        let mut headers = StrategyMap::with_strategy(AsciiCaseInsensitive);
+        assert_eq!(headers.insert(String::from("Content-Type"), "text/html"), None);
+        assert_eq!(headers.insert(String::from("content-type"), "text/plain"), Some("text/html"));
+        assert_eq!(headers.get("CONTENT-TYPE"), Some(&"text/plain"));
+        *headers.entry(String::from("Content-TYPE")).or_insert("") = "image/png";
+        headers.entry(String::from("Accept")).or_insert("*/*");
+        assert_eq!(headers.len(), 2);
+        // the first spelling stays
+        let mut keys: Vec<_> = headers.iter().map(|(key, _)| key.as_str()).collect();
+        keys.sort();
+        assert_eq!(keys, ["Accept", "Content-Type"]);
+        assert_eq!(headers.remove("content-type"), Some("image/png"));
+        assert!(!headers.contains_key("Content-Type"));
+        assert!(headers.contains_key("accept"));
+    }
+}
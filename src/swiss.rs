@@ -0,0 +1,460 @@
+//! a SwissTable-style open-addressing table
+//!
+//! Next to the slots sits one control byte per slot: `EMPTY`, `DELETED`,
+//! or the top 7 bits of the element's hash (its "h2"). Lookups scan the
+//! control bytes a group of 16 at a time, with one SSE2 compare on
+//! x86_64 and a plain loop elsewhere, and only look at slots whose control
+//! byte matches. Probing moves group by group, triangularly, until a group
+//! with an `EMPTY` byte shows the element can't be further on.
+//!
+//! `SwissMap` is a `Hashmap` on `SwissTable`.
+use std::{iter::Flatten, mem, slice};
+
+use crate::TableBackend;
+
+const GROUP_WIDTH: usize = 16;
+const EMPTY: u8 = 0xFF;
+const DELETED: u8 = 0x80;
+
+/// the 7 bits of `hash` stored in the control byte; always < 0x80
+fn h2(hash: u64) -> u8 {
+    (hash >> 57) as u8
+}
+
+/// bit `i` is set for each byte `i` of the group that matched
+#[derive(Clone, Copy)]
+struct BitMask(u16);
+
+impl BitMask {
+    fn any(self) -> bool {
+        self.0 != 0
+    }
+
+    fn lowest(self) -> Option<usize> {
+        self.any().then(|| self.0.trailing_zeros() as usize)
+    }
+
+    /// how many bytes from the start of the group come before the first match
+    fn leading_misses(self) -> usize {
+        self.0.trailing_zeros() as usize
+    }
+
+    /// how many bytes at the end of the group come after the last match
+    fn trailing_misses(self) -> usize {
+        self.0.leading_zeros() as usize
+    }
+}
+
+impl Iterator for BitMask {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let bit = self.lowest()?;
+        self.0 &= self.0 - 1;
+        Some(bit)
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+mod group {
+    use std::arch::x86_64::*;
+
+    use super::{BitMask, EMPTY, GROUP_WIDTH};
+
+    pub(super) struct Group(__m128i);
+
+    impl Group {
+        pub(super) fn load(bytes: &[u8; GROUP_WIDTH]) -> Self {
+            // unaligned load of 16 bytes we hold a reference to; sse2 is
+            // statically enabled
+            Group(unsafe { _mm_loadu_si128(bytes.as_ptr() as *const __m128i) })
+        }
+
+        pub(super) fn match_byte(&self, byte: u8) -> BitMask {
+            unsafe {
+                let cmp = _mm_cmpeq_epi8(self.0, _mm_set1_epi8(byte as i8));
+                BitMask(_mm_movemask_epi8(cmp) as u16)
+            }
+        }
+
+        pub(super) fn match_empty(&self) -> BitMask {
+            self.match_byte(EMPTY)
+        }
+
+        /// both `EMPTY` and `DELETED` have the high bit set, h2 bytes don't
+        pub(super) fn match_empty_or_deleted(&self) -> BitMask {
+            unsafe { BitMask(_mm_movemask_epi8(self.0) as u16) }
+        }
+    }
+}
+
+#[cfg(not(all(target_arch = "x86_64", target_feature = "sse2")))]
+mod group {
+    use super::{BitMask, EMPTY, GROUP_WIDTH};
+
+    pub(super) struct Group([u8; GROUP_WIDTH]);
+
+    impl Group {
+        pub(super) fn load(bytes: &[u8; GROUP_WIDTH]) -> Self {
+            Group(*bytes)
+        }
+
+        fn matching(&self, f: impl Fn(u8) -> bool) -> BitMask {
+            let mut mask = 0;
+            for (i, &byte) in self.0.iter().enumerate() {
+                mask |= (f(byte) as u16) << i;
+            }
+            BitMask(mask)
+        }
+
+        pub(super) fn match_byte(&self, byte: u8) -> BitMask {
+            self.matching(|b| b == byte)
+        }
+
+        pub(super) fn match_empty(&self) -> BitMask {
+            self.match_byte(EMPTY)
+        }
+
+        pub(super) fn match_empty_or_deleted(&self) -> BitMask {
+            self.matching(|b| b & 0x80 != 0)
+        }
+    }
+}
+
+use group::Group;
+
+/// Position of an element inside a `SwissTable`.
+///
+/// A slot stays valid until the table is modified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Slot {
+    index: usize,
+}
+
+#[derive(Clone)]
+struct Bucket<T> {
+    hash: u64,
+    value: T,
+}
+
+/// Iterator over a `SwissTable`'s elements, from `SwissTable::iter`.
+pub struct Iter<'a, T> {
+    inner: Flatten<slice::Iter<'a, Option<Bucket<T>>>>,
+}
+
+#[derive(Clone)]
+pub struct SwissTable<T> {
+    // one byte per slot, then the first GROUP_WIDTH bytes again so a
+    // group can be loaded from any slot without wrapping
+    ctrl: Vec<u8>,
+    // a power of two long (at least GROUP_WIDTH), or empty
+    slots: Vec<Option<Bucket<T>>>,
+    items: usize,
+    // how many more `EMPTY` slots can be filled before rehashing
+    growth_left: usize,
+}
+
+/// usable slots out of `buckets`, for a load factor of 7/8
+fn capacity_of(buckets: usize) -> usize {
+    buckets / 8 * 7
+}
+
+impl<T> SwissTable<T> {
+    pub const fn new() -> Self {
+        SwissTable {
+            ctrl: Vec::new(),
+            slots: Vec::new(),
+            items: 0,
+            growth_left: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+
+    /// number of slots, always zero or a power of two
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn mask(&self) -> usize {
+        self.slots.len() - 1
+    }
+
+    fn group(&self, pos: usize) -> Group {
+        Group::load(self.ctrl[pos..pos + GROUP_WIDTH].try_into().unwrap())
+    }
+
+    fn set_ctrl(&mut self, index: usize, byte: u8) {
+        self.ctrl[index] = byte;
+        if index < GROUP_WIDTH {
+            self.ctrl[self.slots.len() + index] = byte;
+        }
+    }
+
+    /// the start of each group to visit for `hash`, forever
+    fn probe_seq(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let mask = self.mask();
+        let mut pos = hash as usize & mask;
+        let mut stride = 0;
+        std::iter::from_fn(move || {
+            let current = pos;
+            stride += GROUP_WIDTH;
+            pos = (pos + stride) & mask;
+            Some(current)
+        })
+    }
+
+    pub fn find(&self, hash: u64, eq: impl FnMut(&T) -> bool) -> Option<Slot> {
+        self.find_probed(hash, eq).0
+    }
+
+    /// `find`, also counting the slots it looked at: those whose control
+    /// byte matched, the rest being skipped a group at a time
+    pub fn find_probed(&self, hash: u64, mut eq: impl FnMut(&T) -> bool) -> (Option<Slot>, usize) {
+        if self.slots.is_empty() {
+            return (None, 0);
+        }
+        let tag = h2(hash);
+        let mut probes = 0;
+        for pos in self.probe_seq(hash) {
+            let group = self.group(pos);
+            for bit in group.match_byte(tag) {
+                let index = (pos + bit) & self.mask();
+                if let Some(bucket) = &self.slots[index] {
+                    probes += 1;
+                    if bucket.hash == hash && eq(&bucket.value) {
+                        return (Some(Slot { index }), probes);
+                    }
+                }
+            }
+            if group.match_empty().any() {
+                return (None, probes);
+            }
+        }
+        unreachable!("probe sequence is endless")
+    }
+
+    /// # Panics
+    ///
+    /// if `slot` doesn't point at an element (e.g. it is stale)
+    pub fn slot(&self, slot: Slot) -> &T {
+        &self.slots[slot.index].as_ref().expect("stale slot").value
+    }
+
+    /// # Panics
+    ///
+    /// if `slot` doesn't point at an element (e.g. it is stale)
+    pub fn slot_mut(&mut self, slot: Slot) -> &mut T {
+        &mut self.slots[slot.index].as_mut().expect("stale slot").value
+    }
+
+    /// Makes room for `additional` more elements without rehashing,
+    /// growing if the load factor would go above 7/8 or clearing out
+    /// tombstones if they take up the room.
+    pub fn reserve(&mut self, additional: usize) {
+        if additional <= self.growth_left {
+            return;
+        }
+        let needed = self.items + additional;
+        let mut buckets = self.slots.len().max(GROUP_WIDTH);
+        while needed > capacity_of(buckets) {
+            buckets *= 2;
+        }
+        self.resize(buckets);
+    }
+
+    fn resize(&mut self, buckets: usize) {
+        let old = mem::replace(&mut self.slots, (0..buckets).map(|_| None).collect());
+        self.ctrl = vec![EMPTY; buckets + GROUP_WIDTH];
+        self.items = 0;
+        self.growth_left = capacity_of(buckets);
+        for bucket in old.into_iter().flatten() {
+            self.insert_no_grow(bucket.hash, bucket.value);
+        }
+    }
+
+    /// Inserts `value` under `hash` without checking for an equal element;
+    /// callers wanting map semantics must `find` first.
+    pub fn insert(&mut self, hash: u64, value: T) -> Slot {
+        self.reserve(1);
+        self.insert_no_grow(hash, value)
+    }
+
+    /// Like `insert`, for callers that already called `reserve`.
+    ///
+    /// # Panics
+    ///
+    /// if the table has no room left
+    pub fn insert_no_grow(&mut self, hash: u64, value: T) -> Slot {
+        let index = self
+            .probe_seq(hash)
+            .find_map(|pos| {
+                let bit = self.group(pos).match_empty_or_deleted().lowest()?;
+                Some((pos + bit) & self.mask())
+            })
+            .unwrap();
+        if self.ctrl[index] == EMPTY {
+            assert!(self.growth_left > 0, "SwissTable is full");
+            self.growth_left -= 1;
+        }
+        self.set_ctrl(index, h2(hash));
+        self.slots[index] = Some(Bucket { hash, value });
+        self.items += 1;
+        Slot { index }
+    }
+
+    pub fn erase(&mut self, slot: Slot) -> T {
+        let removed = self.slots[slot.index].take().expect("stale slot");
+        self.items -= 1;
+        // a lookup only stops at an `EMPTY` byte inside a full group. If
+        // no group around this slot could have been full, it can go back
+        // to `EMPTY`; otherwise probes may have passed it, so leave a
+        // tombstone.
+        let before = (slot.index.wrapping_sub(GROUP_WIDTH)) & self.mask();
+        let full_before = self.group(before).match_empty().trailing_misses();
+        let full_after = self.group(slot.index).match_empty().leading_misses();
+        if full_before + full_after >= GROUP_WIDTH {
+            self.set_ctrl(slot.index, DELETED);
+        } else {
+            self.set_ctrl(slot.index, EMPTY);
+            self.growth_left += 1;
+        }
+        removed.value
+    }
+
+    pub fn remove(&mut self, hash: u64, eq: impl FnMut(&T) -> bool) -> Option<T> {
+        let slot = self.find(hash, eq)?;
+        Some(self.erase(slot))
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            inner: self.slots.iter().flatten(),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> + '_ {
+        self.slots.iter_mut().flatten().map(|bucket| &mut bucket.value)
+    }
+}
+
+impl<T> Default for SwissTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        self.inner.next().map(|bucket| &bucket.value)
+    }
+}
+
+impl<K, V> TableBackend<K, V> for SwissTable<(K, V)> {
+    type Slot = Slot;
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn bucket_count(&self) -> usize {
+        self.capacity()
+    }
+
+    fn find(&self, hash: u64, eq: impl FnMut(&(K, V)) -> bool) -> Option<Slot> {
+        self.find(hash, eq)
+    }
+
+    fn find_probed(&self, hash: u64, eq: impl FnMut(&(K, V)) -> bool) -> (Option<Slot>, usize) {
+        self.find_probed(hash, eq)
+    }
+
+    fn slot(&self, slot: Slot) -> &(K, V) {
+        self.slot(slot)
+    }
+
+    fn slot_mut(&mut self, slot: Slot) -> &mut (K, V) {
+        self.slot_mut(slot)
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.reserve(additional)
+    }
+
+    fn insert_no_grow(&mut self, hash: u64, entry: (K, V)) -> Slot {
+        self.insert_no_grow(hash, entry)
+    }
+
+    fn erase(&mut self, slot: Slot) -> (K, V) {
+        self.erase(slot)
+    }
+
+    fn resize(&mut self) {
+        self.resize((self.capacity() * 2).max(GROUP_WIDTH))
+    }
+
+    fn next_run(&self, pos: &mut usize) -> &[(K, V)] {
+        // full slots have the high control bit clear; the control bytes
+        // are far denser to scan than the slots
+        let ctrl = self.ctrl.get(*pos..self.slots.len()).unwrap_or_default();
+        let Some(skipped) = ctrl.iter().position(|&byte| byte & 0x80 == 0) else {
+            *pos = self.slots.len();
+            return &[];
+        };
+        let index = *pos + skipped;
+        *pos = index + 1;
+        std::slice::from_ref(&self.slots[index].as_ref().expect("full slot").value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table() {
+        let mut table = SwissTable::new();
+        // same h2 and home group for everything
+        let hash = |v: u64| (0x55 << 57) | (v % 3);
+        for v in 0..100u64 {
+            let slot = table.insert(hash(v), v);
+            assert_eq!(*table.slot(slot), v);
+        }
+        for v in (0..100u64).step_by(3) {
+            assert_eq!(table.remove(hash(v), |&e| e == v), Some(v));
+        }
+        for v in 0..100u64 {
+            assert_eq!(table.find(hash(v), |&e| e == v).is_some(), v % 3 != 0);
+        }
+        assert_eq!(table.len(), 66);
+        assert_eq!(table.iter().count(), 66);
+    }
+
+    #[test]
+    fn map() {
+        let mut map: crate::SwissMap<_, _> = crate::SwissMap::default();
+        // churn so tombstones pile up and get rehashed away
+        for round in 0..4u32 {
+            for i in 0..5_000u32 {
+                assert_eq!(map.insert((round, i), i), None);
+            }
+            assert_eq!(map.insert((round, 7), 0), Some(7));
+            for i in 0..5_000 {
+                if i % 4 != 0 {
+                    assert!(map.remove(&(round, i)).is_some());
+                }
+            }
+        }
+        assert_eq!(map.len(), 4 * 1_250);
+        assert_eq!(map.get(&(2, 8)), Some(&8));
+        assert_eq!(map.get(&(2, 9)), None);
+        assert!(map.table.capacity() <= 16384);
+    }
+}
@@ -0,0 +1,161 @@
+//! all-or-nothing edits to a `Hashmap`
+//!
+//! `Hashmap::transaction` hands out a `Transaction` that edits the map in
+//! place and logs how to undo each change. `commit` keeps the changes;
+//! `rollback`, or dropping the transaction (say, on an early `?` return),
+//! undoes them newest first.
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+};
+
+use crate::Hashmap;
+
+enum Undo<K, V> {
+    /// the key was new
+    Remove(K),
+    /// the key held this value
+    Restore(K, V),
+}
+
+/// Pending edits to a map, see `Hashmap::transaction`.
+///
+/// Replaced and removed values stay in the undo log until the
+/// transaction ends, so `insert` and `remove` lend them out instead of
+/// returning them.
+pub struct Transaction<'a, K: Eq + Hash, V, S: BuildHasher> {
+    map: &'a mut Hashmap<K, V, S>,
+    undo: Vec<Undo<K, V>>,
+}
+
+impl<K, V, S> Hashmap<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher,
+{
+    /// Starts a group of edits that are undone unless committed.
+    pub fn transaction(&mut self) -> Transaction<'_, K, V, S> {
+        Transaction {
+            map: self,
+            undo: Vec::new(),
+        }
+    }
+}
+
+impl<K, V, S> Transaction<'_, K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher,
+{
+    /// the value just moved into the log
+    fn logged(&self) -> Option<&V> {
+        match self.undo.last()? {
+            Undo::Restore(_, value) => Some(value),
+            Undo::Remove(_) => None,
+        }
+    }
+
+    /// Like `Hashmap::insert`; the replaced value, if any, is lent from
+    /// the undo log.
+    pub fn insert(&mut self, key: K, value: V) -> Option<&V> {
+        let undo_key = key.clone();
+        self.undo.push(match self.map.insert(key, value) {
+            None => Undo::Remove(undo_key),
+            Some(old) => Undo::Restore(undo_key, old),
+        });
+        self.logged()
+    }
+
+    /// Like `Hashmap::remove`; the removed value is lent from the undo log.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let (key, value) = self.map.remove_entry(key)?;
+        self.undo.push(Undo::Restore(key, value));
+        self.logged()
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.get(key)
+    }
+
+    /// Keeps every edit.
+    pub fn commit(mut self) {
+        self.undo.clear();
+    }
+
+    /// Undoes every edit, newest first; the same as dropping `self`.
+    pub fn rollback(self) {}
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> Drop for Transaction<'_, K, V, S> {
+    fn drop(&mut self) {
+        while let Some(undo) = self.undo.pop() {
+            match undo {
+                Undo::Remove(key) => {
+                    self.map.remove(&key);
+                }
+                Undo::Restore(key, value) => {
+                    self.map.insert(key, value);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Hashmap<&'static str, u32> {
+        let mut map = Hashmap::new();
+        map.insert("a", 1);
+        map.insert("b", 2);
+        map
+    }
+
+    #[test]
+    fn rollback() {
+        let mut map = sample();
+        let mut tx = map.transaction();
+        assert_eq!(tx.insert("a", 10), Some(&1));
+        assert_eq!(tx.insert("c", 3), None);
+        assert_eq!(tx.remove("b"), Some(&2));
+        assert_eq!(tx.remove("b"), None);
+        assert_eq!(tx.insert("b", 20), None);
+        assert_eq!(tx.get("a"), Some(&10));
+        tx.rollback();
+        assert!(map == sample());
+    }
+
+    #[test]
+    fn commit() {
+        let mut map = sample();
+        let mut tx = map.transaction();
+        tx.insert("a", 10);
+        tx.remove("b");
+        tx.commit();
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get("a"), Some(&10));
+    }
+
+    #[test]
+    fn dropped_on_error() {
+        fn update(map: &mut Hashmap<&'static str, u32>) -> Result<(), &'static str> {
+            let mut tx = map.transaction();
+            tx.insert("a", 10);
+            tx.get("missing").ok_or("no such key")?;
+            tx.commit();
+            Ok(())
+        }
+        let mut map = sample();
+        assert_eq!(update(&mut map), Err("no such key"));
+        assert!(map == sample());
+    }
+}
@@ -0,0 +1,184 @@
+//! a map that remembers what it looked like at earlier versions
+//!
+//! `VersionedMap` keeps, per key, the values it had since the first
+//! snapshot, each tagged with the version it was written at. Taking a
+//! snapshot is O(1): it only closes the current version. `get_at` finds
+//! the value a key had at a version by binary search over the key's
+//! chain. Nothing is ever forgotten, so this suits audit trails and
+//! config history rather than hot, high-churn maps.
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+};
+
+use crate::{DefaultHashBuilder, Hashmap};
+
+/// A point in a `VersionedMap`'s history, from `VersionedMap::snapshot`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version(u64);
+
+pub struct VersionedMap<K, V, S = DefaultHashBuilder> {
+    // oldest first; `None` marks a removal
+    chains: Hashmap<K, Vec<(u64, Option<V>)>, S>,
+    // the version writes go to, above every snapshot taken
+    current: u64,
+    names: Hashmap<String, Version>,
+    live: usize,
+}
+
+impl<K, V> VersionedMap<K, V> {
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V> Default for VersionedMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, S> VersionedMap<K, V, S> {
+    pub fn with_hasher(hash_builder: S) -> Self {
+        VersionedMap {
+            chains: Hashmap::with_hasher(hash_builder),
+            current: 0,
+            names: Hashmap::new(),
+            live: 0,
+        }
+    }
+
+    /// keys present now
+    pub fn len(&self) -> usize {
+        self.live
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.live == 0
+    }
+
+    /// Freezes the map as it is now, naming the version `name` (replacing
+    /// an earlier version of that name). Later writes don't change what
+    /// `get_at` sees at the returned version.
+    pub fn snapshot(&mut self, name: impl Into<String>) -> Version {
+        let version = Version(self.current);
+        self.current += 1;
+        self.names.insert(name.into(), version);
+        version
+    }
+
+    pub fn version(&self, name: &str) -> Option<Version> {
+        self.names.get(name).copied()
+    }
+}
+
+/// the value a chain held at `version`, if any
+fn value_at<V>(chain: &[(u64, Option<V>)], version: u64) -> Option<&V> {
+    let newer = chain.partition_point(|&(written, _)| written <= version);
+    chain[..newer].last()?.1.as_ref()
+}
+
+/// writes `value` (`None` for a removal) to a chain at version `current`
+fn write<V>(chain: &mut Vec<(u64, Option<V>)>, current: u64, value: Option<V>) {
+    match chain.last_mut() {
+        // no snapshot since the last write, so nothing can see it
+        Some((written, old)) if *written == current => *old = value,
+        _ => chain.push((current, value)),
+    }
+}
+
+impl<K, V, S> VersionedMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Inserts at the current version; returns whether the key was present.
+    pub fn insert(&mut self, key: K, value: V) -> bool {
+        let chain = self.chains.entry(key).or_default();
+        let was_live = matches!(chain.last(), Some((_, Some(_))));
+        write(chain, self.current, Some(value));
+        if !was_live {
+            self.live += 1;
+        }
+        was_live
+    }
+
+    /// Removes at the current version; snapshots taken before still see
+    /// the key. Returns whether it was present.
+    pub fn remove<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        match self.chains.get_mut(key) {
+            Some(chain) if matches!(chain.last(), Some((_, Some(_)))) => {
+                write(chain, self.current, None);
+                self.live -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.chains.get(key)?.last()?.1.as_ref()
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// What `key` mapped to when `version` was taken.
+    pub fn get_at<Q>(&self, version: Version, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        value_at(self.chains.get(key)?, version.0)
+    }
+
+    /// The entries as of `version`, in no particular order.
+    pub fn iter_at(&self, version: Version) -> impl Iterator<Item = (&K, &V)> + '_ {
+        (&self.chains)
+            .into_iter()
+            .filter_map(move |(key, chain)| Some((key, value_at(chain, version.0)?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history() {
+        let mut config = VersionedMap::new();
+        config.insert("port", 80);
+        config.insert("host", 1);
+        let v1 = config.snapshot("v1");
+        config.insert("port", 8080);
+        config.insert("port", 8081);
+        assert!(config.remove("host"));
+        assert!(!config.remove("host"));
+        let v2 = config.snapshot("v2");
+        config.insert("host", 2);
+
+        assert_eq!(config.version("v1"), Some(v1));
+        assert_eq!(config.get_at(v1, "port"), Some(&80));
+        assert_eq!(config.get_at(v1, "host"), Some(&1));
+        assert_eq!(config.get_at(v2, "port"), Some(&8081));
+        assert_eq!(config.get_at(v2, "host"), None);
+        assert_eq!(config.get("host"), Some(&2));
+        assert_eq!(config.len(), 2);
+        assert_eq!(config.iter_at(v2).count(), 1);
+        // one value per snapshot interval, not per write
+        assert_eq!(config.chains.get("port").unwrap().len(), 2);
+    }
+}